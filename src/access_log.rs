@@ -0,0 +1,268 @@
+//! RSPI-BIOS access log
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::io::Write as _;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderName, Method, StatusCode, Uri, Version},
+    middleware::Next,
+    response::Response,
+};
+use bytes::Bytes;
+use http_body::{Frame, SizeHint};
+use time::OffsetDateTime;
+
+use crate::listeners::TlsConnection;
+use crate::log_file::{LogRotationArgs, RotatingFile};
+
+/// Format of each line in `access.log`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum AccessLogFormat {
+    /// Common Log Format
+    Common,
+    /// Combined Log Format followed by the latency in milliseconds
+    Combined,
+    /// JSON object per line
+    Json,
+}
+
+/// CLI parameters for `access.log`, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct AccessLogArgs {
+    /// Don't write `access.log` (placed next to `debug.log`)
+    #[arg(long)]
+    pub(crate) disable_access_log: bool,
+
+    #[arg(long, value_enum, default_value_t = AccessLogFormat::Combined)]
+    access_log_format: AccessLogFormat,
+}
+
+/// Rotating `access.log` shared by every listener.
+pub(crate) struct AccessLog {
+    format: AccessLogFormat,
+    file: Mutex<RotatingFile>,
+}
+
+impl AccessLog {
+    pub(crate) fn open(
+        args: &AccessLogArgs,
+        path: PathBuf,
+        rotation: LogRotationArgs,
+    ) -> std::io::Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            format: args.access_log_format,
+            file: Mutex::new(RotatingFile::open(path, rotation)?),
+        }))
+    }
+
+    fn write(&self, line: &str) {
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            tracing::warn!(error = %e, "Failed to write to access log");
+        }
+    }
+}
+
+/// Request details kept until the response body has been sent.
+struct Entry {
+    time: OffsetDateTime,
+    started: Instant,
+    remote_addr: Option<SocketAddr>,
+    client_subject: Option<Arc<str>>,
+    method: Method,
+    uri: Uri,
+    version: Version,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    status: StatusCode,
+}
+
+fn header_string(headers: &HeaderMap, name: &HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+}
+
+/// Escapes values written between quotes in Common/Combined Log Format lines.
+fn escape(value: &str) -> String {
+    value.escape_default().collect()
+}
+
+impl Entry {
+    fn path_and_query(&self) -> &str {
+        self.uri.path_and_query().map_or("/", |p| p.as_str())
+    }
+
+    fn format(&self, format: AccessLogFormat, bytes: u64) -> String {
+        let latency_ms = self.started.elapsed().as_secs_f64() * 1_000.0;
+        // Unix socket listeners use `0.0.0.0:0`
+        let remote_ip = self
+            .remote_addr
+            .filter(|a| a.port() != 0)
+            .map_or_else(|| "-".to_string(), |a| a.ip().to_string());
+
+        let common = || {
+            let time = self
+                .time
+                .format(time::macros::format_description!(
+                    "[day]/[month repr:short]/[year]:[hour]:[minute]:[second] [offset_hour sign:mandatory][offset_minute]"
+                ))
+                .unwrap_or_default();
+            let bytes = if bytes == 0 {
+                "-".to_string()
+            } else {
+                bytes.to_string()
+            };
+            format!(
+                "{remote_ip} - - [{time}] \"{} {} {:?}\" {} {bytes}",
+                self.method,
+                escape(self.path_and_query()),
+                self.version,
+                self.status.as_u16()
+            )
+        };
+
+        match format {
+            AccessLogFormat::Common => format!("{}\n", common()),
+            AccessLogFormat::Combined => format!(
+                "{} \"{}\" \"{}\" {latency_ms:.3}\n",
+                common(),
+                escape(self.referer.as_deref().unwrap_or("-")),
+                escape(self.user_agent.as_deref().unwrap_or("-")),
+            ),
+            AccessLogFormat::Json => {
+                let line = serde_json::json!({
+                    "time": self
+                        .time
+                        .format(&time::format_description::well_known::Rfc3339)
+                        .unwrap_or_default(),
+                    "remote_addr": remote_ip,
+                    "client_subject": self.client_subject.as_deref(),
+                    "method": self.method.as_str(),
+                    "uri": self.path_and_query(),
+                    "version": format!("{:?}", self.version),
+                    "status": self.status.as_u16(),
+                    "bytes": bytes,
+                    "latency_ms": latency_ms,
+                    "referer": self.referer,
+                    "user_agent": self.user_agent,
+                });
+                format!("{line}\n")
+            }
+        }
+    }
+}
+
+/// Response body that counts the bytes sent and writes the [`Entry`] once it is finished or dropped e.g. when an SSE
+/// client disconnects.
+struct LoggedBody {
+    inner: Body,
+    bytes: u64,
+    entry: Option<Entry>,
+    access_log: Arc<AccessLog>,
+}
+
+impl LoggedBody {
+    fn finish(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.access_log
+                .write(&entry.format(self.access_log.format, self.bytes));
+        }
+    }
+}
+
+impl http_body::Body for LoggedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    self.bytes += data.len() as u64;
+                }
+            }
+            Poll::Ready(None | Some(Err(_))) => self.finish(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Writes a line to the [`AccessLog`] for every request with the status, bytes sent and latency.
+///
+/// See: <https://httpd.apache.org/docs/2.4/logs.html#accesslog>
+pub(crate) async fn access_log_middleware(
+    State(access_log): State<Arc<AccessLog>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let time = OffsetDateTime::now_utc();
+    let started = Instant::now();
+    let remote_addr = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    let client_subject = request
+        .extensions()
+        .get::<TlsConnection>()
+        .and_then(|t| t.client_subject.clone());
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let version = request.version();
+    let referer = header_string(request.headers(), &header::REFERER);
+    let user_agent = header_string(request.headers(), &header::USER_AGENT);
+
+    let response = next.run(request).await;
+
+    let entry = Entry {
+        time,
+        started,
+        remote_addr,
+        client_subject,
+        method,
+        uri,
+        version,
+        referer,
+        user_agent,
+        status: response.status(),
+    };
+
+    response.map(|inner| {
+        Body::new(LoggedBody {
+            inner,
+            bytes: 0,
+            entry: Some(entry),
+            access_log,
+        })
+    })
+}
@@ -0,0 +1,212 @@
+//! RSPI-BIOS admin API
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+
+use base64::Engine as _;
+
+use crate::{log_filter, AppState};
+
+/// Cookie holding the session ID after logging in through the SETUP screen.
+pub(crate) const SESSION_COOKIE: &str = "rspi_bios_session";
+
+/// CLI parameters for the admin API, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct AdminArgs {
+    /// File containing the bearer token for `/admin/*`, the admin API is disabled if not set
+    #[arg(long)]
+    pub(crate) admin_token_file: Option<PathBuf>,
+
+    /// How long a log filter set through the admin API lasts by default before reverting
+    #[arg(long, value_parser = crate::parse_duration, default_value = "600")]
+    pub(crate) log_filter_revert_after: Duration,
+
+    /// How long a SETUP login with the admin token lasts
+    #[arg(long, value_parser = crate::parse_duration, default_value = "3600")]
+    pub(crate) admin_session_duration: Duration,
+}
+
+/// Reads a token e.g. the admin token, trailing whitespace is ignored.
+///
+/// Returns [`None`] if the file can't be read or is empty.
+pub(crate) async fn read_token(path: &PathBuf) -> Option<String> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(t) if !t.trim_end().is_empty() => Some(t.trim_end().to_string()),
+        Ok(_) => {
+            tracing::error!("Token file {} is empty", path.display());
+            None
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to read token from {}", path.display());
+            None
+        }
+    }
+}
+
+/// Compares in constant time (for a given length) so the token can't be guessed byte by byte.
+pub(crate) fn token_matches(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len()
+        && expected
+            .bytes()
+            .zip(provided.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Browser sessions created by logging in with the admin token, kept in memory so restarting logs everyone out.
+pub(crate) struct Sessions {
+    duration: Duration,
+    sessions: std::sync::Mutex<HashMap<String, Session>>,
+}
+
+struct Session {
+    expires_at: Instant,
+    /// Sent back in forms that act on the system, see [`Sessions::csrf_matches`]
+    csrf_token: String,
+}
+
+/// Random 256-bit ID, used for sessions and CSRF tokens.
+fn random_id() -> Option<String> {
+    let mut bytes = [0_u8; 32];
+    if let Err(e) = getrandom::getrandom(&mut bytes) {
+        tracing::error!(error = %e, "Failed to generate random ID");
+        return None;
+    }
+    Some(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// Returns the value of the `name` cookie, if any.
+pub(crate) fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|c| {
+            c.trim()
+                .split_once('=')
+                .filter(|(n, _)| *n == name)
+                .map(|(_, value)| value)
+        })
+}
+
+impl Sessions {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            sessions: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Creates a session with a random 256-bit ID and returns the `Set-Cookie` value for it.
+    ///
+    /// `secure` should only be `false` for plain HTTP listeners, browsers ignore secure cookies over HTTP.
+    pub(crate) fn create(&self, secure: bool) -> Option<String> {
+        let id = random_id()?;
+        let now = Instant::now();
+        let session = Session {
+            expires_at: now + self.duration,
+            csrf_token: random_id()?,
+        };
+
+        {
+            let mut sessions = self.sessions.lock().ok()?;
+            sessions.retain(|_, s| s.expires_at > now);
+            sessions.insert(id.clone(), session);
+        }
+
+        Some(format!(
+            "{SESSION_COOKIE}={id}; Max-Age={}; Path=/; HttpOnly; SameSite=Strict{}",
+            self.duration.as_secs(),
+            if secure { "; Secure" } else { "" }
+        ))
+    }
+
+    /// Ends the session in the request's cookie and returns the `Set-Cookie` value that clears it.
+    pub(crate) fn remove(&self, headers: &HeaderMap) -> String {
+        if let (Some(id), Ok(mut sessions)) =
+            (cookie(headers, SESSION_COOKIE), self.sessions.lock())
+        {
+            sessions.remove(id);
+        }
+        format!("{SESSION_COOKIE}=; Max-Age=0; Path=/; HttpOnly; SameSite=Strict")
+    }
+
+    /// Returns the CSRF token of the request's session if it hasn't expired.
+    pub(crate) fn csrf_token(&self, headers: &HeaderMap) -> Option<String> {
+        let id = cookie(headers, SESSION_COOKIE)?;
+        self.sessions
+            .lock()
+            .ok()?
+            .get(id)
+            .filter(|s| s.expires_at > Instant::now())
+            .map(|s| s.csrf_token.clone())
+    }
+
+    /// Returns `true` if the request's cookie belongs to a session that hasn't expired.
+    pub(crate) fn is_valid(&self, headers: &HeaderMap) -> bool {
+        self.csrf_token(headers).is_some()
+    }
+
+    /// Returns `true` if `provided` is the CSRF token of the request's session.
+    ///
+    /// The cookie alone is sent by any page the browser is on, only this instance's pages know the token.
+    pub(crate) fn csrf_matches(&self, headers: &HeaderMap, provided: &str) -> bool {
+        self.csrf_token(headers)
+            .is_some_and(|t| token_matches(&t, provided))
+    }
+}
+
+/// Rejects requests without `Authorization: Bearer <admin token>`.
+pub(crate) async fn require_admin_token(
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    state: State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    let authorized = match (&state.admin_token, provided) {
+        (Some(expected), Some(provided)) => token_matches(expected, provided),
+        _ => false,
+    };
+    if !authorized {
+        tracing::warn!(uri = %request.uri(), "Rejected unauthorized admin request from {addr}");
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Creates the routes under `/admin`, all of them require the admin token.
+pub(crate) fn admin_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route(
+            "/admin/log-filter",
+            get(log_filter::get_handler)
+                .put(log_filter::put_handler)
+                .delete(log_filter::delete_handler),
+        )
+        .route_layer(middleware::from_fn_with_state(state, require_admin_token))
+}
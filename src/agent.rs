@@ -0,0 +1,161 @@
+//! RSPI-BIOS agent pushing to a hub
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use sysinfo::System;
+
+use crate::admin::read_token;
+use crate::api::SystemInfo;
+use crate::client::build_client;
+use crate::hub::AgentPush;
+use crate::snapshot::SystemSnapshot;
+use crate::AppState;
+
+/// CLI parameters for pushing snapshots to a hub that can't reach this instance e.g. behind NAT, flattened into
+/// `Args`.
+#[derive(clap::Args, Clone, Debug)]
+#[allow(clippy::struct_field_names)]
+pub(crate) struct AgentArgs {
+    /// Base URL of the hub to push every snapshot to, enables agent mode e.g. `https://hub.example.com:3000`
+    #[arg(long)]
+    agent_hub_url: Option<reqwest::Url>,
+
+    /// File containing the token expected by the hub (`--hub-agent-token-file` on the hub)
+    #[arg(long)]
+    agent_token_file: Option<PathBuf>,
+
+    /// Name of this node on the hub, the host name if not set
+    #[arg(long)]
+    agent_name: Option<String>,
+
+    /// Snapshots kept while the hub is unreachable and sent once it is back, the oldest are dropped first
+    #[arg(long, default_value_t = 720)]
+    agent_buffer_size: usize,
+
+    /// PEM certificate to trust for the hub in addition to the system roots
+    #[arg(long)]
+    agent_ca_file: Option<PathBuf>,
+
+    /// Don't verify the hub certificate
+    #[arg(long)]
+    agent_insecure: bool,
+
+    /// PEM file with a certificate and its private key, presented to a hub that requires a client certificate
+    #[arg(long)]
+    agent_client_cert: Option<PathBuf>,
+}
+
+/// Pushes snapshots to `POST /hub/push` of the hub.
+pub(crate) struct Agent {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    token: String,
+    name: String,
+    buffer_size: usize,
+}
+
+impl Agent {
+    /// Returns [`None`] if `--agent-hub-url` isn't set.
+    pub(crate) async fn new(args: &AgentArgs) -> Result<Option<Self>, String> {
+        let Some(hub_url) = &args.agent_hub_url else {
+            return Ok(None);
+        };
+        let Some(token_file) = &args.agent_token_file else {
+            return Err("--agent-hub-url needs --agent-token-file".to_string());
+        };
+        let token = read_token(token_file)
+            .await
+            .ok_or_else(|| format!("no agent token in {}", token_file.display()))?;
+        let url = hub_url
+            .join("hub/push")
+            .map_err(|e| format!("Invalid URL {hub_url}. Error: {e}"))?;
+
+        Ok(Some(Self {
+            client: build_client(
+                args.agent_ca_file.as_deref(),
+                args.agent_client_cert.as_deref(),
+                args.agent_insecure,
+            )
+            .await?,
+            url,
+            token,
+            name: args
+                .agent_name
+                .clone()
+                .or_else(System::host_name)
+                .unwrap_or_else(|| env!("CARGO_CRATE_NAME").to_string()),
+            buffer_size: args.agent_buffer_size.max(1),
+        }))
+    }
+
+    async fn push(
+        &self,
+        system: &SystemInfo,
+        buffer: &VecDeque<SystemSnapshot>,
+    ) -> Result<(), String> {
+        let body = serde_json::to_string(&AgentPush {
+            name: self.name.clone(),
+            system: system.clone(),
+            snapshots: buffer.iter().cloned().collect(),
+        })
+        .map_err(|e| e.to_string())?;
+
+        let response = self
+            .client
+            .post(self.url.clone())
+            .bearer_auth(&self.token)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let status_code = response.status();
+        if !status_code.is_success() {
+            return Err(format!("{status_code} from {}", self.url));
+        }
+        Ok(())
+    }
+}
+
+/// Pushes every [`SystemSnapshot`] to the hub, buffering them while it is unreachable.
+///
+/// The buffer is sent in one request once the hub is back, so it only needs to accept a single push at a time.
+pub(crate) async fn push_system_messages(agent: Agent, state: Arc<AppState>) {
+    tracing::info!("Pushing snapshots to hub {} as {}", agent.url, agent.name);
+
+    let system = SystemInfo::from_state(&state).await;
+    let mut snapshot_rx = state.snapshot_tx.subscribe();
+    let mut buffer = VecDeque::new();
+    let mut failing = false;
+
+    while snapshot_rx.changed().await.is_ok() {
+        if buffer.len() >= agent.buffer_size {
+            buffer.pop_front();
+        }
+        buffer.push_back(snapshot_rx.borrow_and_update().clone());
+
+        match agent.push(&system, &buffer).await {
+            Ok(()) => {
+                if failing {
+                    tracing::info!(
+                        "Hub is reachable again, sent {} buffered snapshots",
+                        buffer.len()
+                    );
+                    failing = false;
+                }
+                buffer.clear();
+            }
+            Err(e) => {
+                // Logged once instead of every snapshot
+                if !failing {
+                    tracing::error!(error = %e, "Failed to push to hub, buffering up to {} snapshots", agent.buffer_size);
+                    failing = true;
+                }
+            }
+        }
+    }
+}
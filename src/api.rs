@@ -0,0 +1,123 @@
+//! RSPI-BIOS JSON API
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use sysinfo::{Disks, System};
+
+use crate::snapshot::SystemSnapshot;
+use crate::AppState;
+
+/// System information that doesn't change while running.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct SystemInfo {
+    pub(crate) model_name: String,
+    pub(crate) host_name: String,
+    pub(crate) os_version: String,
+    pub(crate) kernel_version: String,
+    pub(crate) cpu_brand: String,
+    pub(crate) cpu_arch: String,
+    pub(crate) cpu_count: usize,
+    /// CPU frequency in MHz.
+    pub(crate) cpu_speed: u64,
+    /// Size of the primary disk in bytes.
+    pub(crate) disk_size: u64,
+    /// `rspi-bios` version.
+    pub(crate) version: String,
+}
+
+/// Body of `GET /api/snapshot` responses.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct ApiSnapshot {
+    pub(crate) system: SystemInfo,
+    pub(crate) snapshot: SystemSnapshot,
+}
+
+impl ApiSnapshot {
+    /// Gets `url` (`/api/snapshot` of another instance).
+    pub(crate) async fn fetch(
+        client: &reqwest::Client,
+        url: &reqwest::Url,
+    ) -> Result<Self, String> {
+        let response = client
+            .get(url.clone())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let status_code = response.status();
+        let body = response.text().await.map_err(|e| e.to_string())?;
+        if !status_code.is_success() {
+            return Err(format!("{status_code} from {url}"));
+        }
+        serde_json::from_str(&body).map_err(|e| e.to_string())
+    }
+}
+
+impl SystemInfo {
+    /// Uses the information cached in [`AppState`], including the fallbacks used when it isn't available.
+    pub(crate) async fn from_state(state: &AppState) -> Self {
+        let (cpu_brand, cpu_count, cpu_speed) = cpu(&*state.system.lock().await);
+        let disk_size = state
+            .disks
+            .lock()
+            .await
+            .first()
+            .map_or(0, sysinfo::Disk::total_space);
+
+        Self {
+            model_name: state.model_name.clone(),
+            host_name: System::host_name().unwrap_or_default(),
+            os_version: state.os_version.lock().await.clone(),
+            kernel_version: state.kernel_version.lock().await.clone(),
+            cpu_brand,
+            cpu_arch: state.cpu_arch.lock().await.clone(),
+            cpu_count,
+            cpu_speed,
+            disk_size,
+            version: env!("RSPI_BIOS_VERSION").to_string(),
+        }
+    }
+
+    /// Collects the information from this machine, used when there is no server to ask e.g. `tui --local`.
+    pub(crate) fn local(system: &System, model_name: String) -> Self {
+        let (cpu_brand, cpu_count, cpu_speed) = cpu(system);
+
+        Self {
+            model_name,
+            host_name: System::host_name().unwrap_or_default(),
+            os_version: System::long_os_version().unwrap_or_default(),
+            kernel_version: System::kernel_version().unwrap_or_default(),
+            cpu_brand,
+            cpu_arch: System::cpu_arch().unwrap_or_default(),
+            cpu_count,
+            cpu_speed,
+            disk_size: Disks::new_with_refreshed_list()
+                .first()
+                .map_or(0, sysinfo::Disk::total_space),
+            version: env!("RSPI_BIOS_VERSION").to_string(),
+        }
+    }
+}
+
+/// Returns the brand, count and frequency of the CPUs.
+pub(crate) fn cpu(system: &System) -> (String, usize, u64) {
+    let first = system.cpus().first();
+    (
+        first.map(|c| c.brand().to_string()).unwrap_or_default(),
+        system.cpus().len(),
+        first.map_or(0, sysinfo::Cpu::frequency),
+    )
+}
+
+/// Handles `GET` requests to `/api/snapshot`, returns the latest [`SystemSnapshot`] with the static system
+/// information e.g. for `rspi-bios tui`.
+pub(crate) async fn snapshot_handler(state: State<Arc<AppState>>) -> Json<ApiSnapshot> {
+    let snapshot = state.snapshot_tx.borrow().clone();
+    Json(ApiSnapshot {
+        system: SystemInfo::from_state(&state).await,
+        snapshot,
+    })
+}
@@ -0,0 +1,160 @@
+//! RSPI-BIOS static assets
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::AppState;
+
+/// A file embedded in the binary at compile time.
+struct Asset {
+    path: &'static str,
+    content_type: &'static str,
+    body: &'static [u8],
+}
+
+/// Files served under `/static/`.
+const ASSETS: &[Asset] = &[
+    Asset {
+        path: "index.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../static/index.css"),
+    },
+    Asset {
+        path: "setup.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../static/setup.css"),
+    },
+    Asset {
+        path: "hub.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../static/hub.css"),
+    },
+    Asset {
+        path: "containers.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../static/containers.css"),
+    },
+    Asset {
+        path: "processes.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../static/processes.css"),
+    },
+    Asset {
+        path: "award/index.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../static/award/index.css"),
+    },
+    Asset {
+        path: "award/setup.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../static/award/setup.css"),
+    },
+    Asset {
+        path: "ami/index.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../static/ami/index.css"),
+    },
+    Asset {
+        path: "ami/setup.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../static/ami/setup.css"),
+    },
+    Asset {
+        path: "phoenix/index.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../static/phoenix/index.css"),
+    },
+    Asset {
+        path: "phoenix/setup.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../static/phoenix/setup.css"),
+    },
+];
+
+/// Weak `ETag` derived from the asset contents.
+///
+/// Weak since the same tag is sent for the compressed and identity bodies, which aren't byte-for-byte identical.
+fn etag(asset: &Asset) -> String {
+    let mut hasher = DefaultHasher::new();
+    asset.body.hash(&mut hasher);
+    format!("W/\"{:016x}\"", hasher.finish())
+}
+
+/// Returns `true` if the client's cached copy is still valid.
+///
+/// `If-None-Match` takes precedence over `If-Modified-Since` and uses the weak comparison as per RFC 9110.
+fn is_not_modified(headers: &HeaderMap, etag: &str, last_modified: SystemTime) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if let Some(if_none_match) = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    {
+        return if_none_match
+            .split(',')
+            .map(|t| t.trim().trim_start_matches("W/"))
+            .any(|t| t == "*" || t == etag);
+    }
+
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+        .is_some_and(|since| last_modified <= since)
+}
+
+/// Handles `GET` requests to `/static/*path`.
+///
+/// Assets are embedded so they can only change when the binary does, the process start time is used as
+/// `Last-Modified`.
+pub(crate) async fn static_handler(
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    state: State<Arc<AppState>>,
+) -> Response {
+    let Some(asset) = ASSETS.iter().find(|a| a.path == path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // HTTP dates only have second precision
+    let last_modified = state
+        .started_at
+        .duration_since(UNIX_EPOCH)
+        .map_or(UNIX_EPOCH, |d| {
+            UNIX_EPOCH + Duration::from_secs(d.as_secs())
+        });
+    let etag = etag(asset);
+
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (
+            header::LAST_MODIFIED,
+            httpdate::fmt_http_date(last_modified),
+        ),
+        // Always revalidate, cheap thanks to `304 Not Modified`
+        (header::CACHE_CONTROL, "no-cache".to_string()),
+    ];
+
+    if is_not_modified(&headers, &etag, last_modified) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+
+    (
+        cache_headers,
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(asset.content_type),
+        )],
+        asset.body,
+    )
+        .into_response()
+}
@@ -0,0 +1,70 @@
+//! RSPI-BIOS boot animation
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue},
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::admin::cookie;
+
+/// Cookie set after the first visit, returning visitors get [`BootMode::Fast`] instead of [`BootMode::Full`].
+pub(crate) const BOOT_COOKIE: &str = "rspi_bios_booted";
+
+/// How the boot screen is played before showing the health screen.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum BootMode {
+    /// Memory test and every boot step, `fast` for returning visitors
+    #[default]
+    Full,
+    /// Memory test result and boot steps at a tenth of the delay
+    Fast,
+    /// Straight to the health screen
+    Skip,
+}
+
+impl BootMode {
+    pub(crate) const fn as_str(self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::Fast => "fast",
+            Self::Skip => "skip",
+        }
+    }
+
+    pub(crate) const fn label(self) -> &'static str {
+        match self {
+            Self::Full => "Full",
+            Self::Fast => "Fast",
+            Self::Skip => "Skip",
+        }
+    }
+
+    /// Picks `?skip=1` or `?skip=0` (replays the full animation) if set, otherwise `default` (`--config-file` or
+    /// `--boot-mode`) shortened for returning visitors. Other values are ignored.
+    pub(crate) fn select(skip: Option<&str>, headers: &HeaderMap, default: Self) -> Self {
+        match skip {
+            Some("0") => Self::Full,
+            Some("1") => Self::Skip,
+            _ if default == Self::Full && cookie(headers, BOOT_COOKIE).is_some() => Self::Fast,
+            _ => default,
+        }
+    }
+
+    /// Sets the [`BOOT_COOKIE`] on the first visit.
+    pub(crate) fn remember(headers: &HeaderMap, response: &mut Response) {
+        if cookie(headers, BOOT_COOKIE).is_some() {
+            return;
+        }
+        if let Ok(set_cookie) = HeaderValue::from_str(&format!(
+            "{BOOT_COOKIE}=1; Max-Age=31536000; Path=/; SameSite=Lax"
+        )) {
+            response
+                .headers_mut()
+                .append(header::SET_COOKIE, set_cookie);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! RSPI-BIOS client for subcommands
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// CLI parameters for subcommands talking to a running instance, flattened into them.
+#[derive(clap::Args, Debug)]
+pub(crate) struct ClientArgs {
+    /// Base URL of the running instance
+    #[arg(long, default_value = "https://localhost:3000")]
+    pub(crate) url: reqwest::Url,
+
+    /// PEM certificate to trust in addition to the system roots e.g. the self-signed `cert.pem`
+    #[arg(long)]
+    ca_file: Option<PathBuf>,
+
+    /// Don't verify the server certificate
+    #[arg(long)]
+    insecure: bool,
+
+    /// PEM file with a certificate and its private key, needed if the instance requires a client certificate
+    #[arg(long)]
+    client_cert: Option<PathBuf>,
+}
+
+impl ClientArgs {
+    /// Creates a client trusting `--ca-file`, errors are meant to be printed to `STDERR`.
+    pub(crate) async fn client(&self) -> Result<reqwest::Client, String> {
+        build_client(
+            self.ca_file.as_deref(),
+            self.client_cert.as_deref(),
+            self.insecure,
+        )
+        .await
+    }
+
+    /// Resolves `path` against `--url`.
+    pub(crate) fn endpoint(&self, path: &str) -> Result<reqwest::Url, String> {
+        self.url
+            .join(path)
+            .map_err(|e| format!("Invalid URL {}. Error: {e}", self.url))
+    }
+}
+
+/// Creates a client for talking to other instances, trusting `ca_file` in addition to the system roots.
+///
+/// `client_cert` is a PEM file with the certificate and its private key, presented to instances that require a
+/// client certificate (`--require-client-cert`).
+pub(crate) async fn build_client(
+    ca_file: Option<&Path>,
+    client_cert: Option<&Path>,
+    insecure: bool,
+) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .danger_accept_invalid_certs(insecure);
+    if let Some(ca_file) = ca_file {
+        let certificate = tokio::fs::read(ca_file)
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|pem| reqwest::Certificate::from_pem(&pem).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Failed to load {}. Error: {e}", ca_file.display()))?;
+        builder = builder.add_root_certificate(certificate);
+    }
+    if let Some(client_cert) = client_cert {
+        let identity = tokio::fs::read(client_cert)
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|pem| reqwest::Identity::from_pem(&pem).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Failed to load {}. Error: {e}", client_cert.display()))?;
+        builder = builder.identity(identity);
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client. Error: {e}"))
+}
@@ -0,0 +1,118 @@
+//! RSPI-BIOS config file
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::boot::BootMode;
+use crate::systemd;
+use crate::theme::Theme;
+use crate::Args;
+
+/// Settings changed through the SETUP screen, stored as TOML in `--config-file`.
+///
+/// Values that aren't set fall back to the matching CLI parameters, set values take precedence over them.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct ConfigFile {
+    /// `--system-refresh-interval` in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) system_refresh_interval: Option<u64>,
+
+    /// `--sse-keep-alive-interval` in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) sse_keep_alive_interval: Option<u64>,
+
+    /// `--theme`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) theme: Option<Theme>,
+
+    /// `--boot-mode`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) boot_mode: Option<BootMode>,
+}
+
+impl ConfigFile {
+    /// Captures the current values of every setting in `args`, used to restore them with "Load Defaults".
+    pub(crate) const fn from_args(args: &Args) -> Self {
+        Self {
+            system_refresh_interval: Some(args.system_refresh_interval.as_secs()),
+            sse_keep_alive_interval: Some(args.sse_keep_alive_interval.as_secs()),
+            theme: Some(args.theme),
+            boot_mode: Some(args.boot_mode),
+        }
+    }
+
+    /// Reads and parses `path`, a missing file is treated as empty.
+    pub(crate) async fn load(path: &Path) -> Result<Self, String> {
+        match tokio::fs::read_to_string(path).await {
+            Ok(contents) => {
+                let config: Self = toml::from_str(&contents).map_err(|e| e.to_string())?;
+                config.validate()?;
+                Ok(config)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Intervals are used with [`tokio::time::interval`] which doesn't accept zero.
+    ///
+    /// The system information collector is what pets the systemd watchdog, so `system_refresh_interval` also has to
+    /// be shorter than its timeout or systemd keeps restarting the service.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("system_refresh_interval", self.system_refresh_interval),
+            ("sse_keep_alive_interval", self.sse_keep_alive_interval),
+        ] {
+            if value == Some(0) {
+                return Err(format!("`{name}` must be at least 1 second"));
+            }
+        }
+
+        if let (Some(secs), Some(watchdog_timeout)) =
+            (self.system_refresh_interval, systemd::watchdog_timeout())
+        {
+            if Duration::from_secs(secs) >= watchdog_timeout {
+                return Err(format!(
+                    "`system_refresh_interval` must be shorter than the systemd watchdog timeout ({}s)",
+                    watchdog_timeout.as_secs()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes to a temporary file first so a crash or power loss doesn't leave a truncated config behind.
+    pub(crate) async fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = toml::to_string(self).map_err(|e| e.to_string())?;
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+
+        tokio::fs::write(&temp_path, contents)
+            .await
+            .map_err(|e| e.to_string())?;
+        tokio::fs::rename(&temp_path, path)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Overrides the settings in `args` with the ones that are set.
+    pub(crate) fn apply(&self, args: &mut Args) {
+        if let Some(secs) = self.system_refresh_interval {
+            args.system_refresh_interval = Duration::from_secs(secs);
+        }
+        if let Some(secs) = self.sse_keep_alive_interval {
+            args.sse_keep_alive_interval = Duration::from_secs(secs);
+        }
+        if let Some(theme) = self.theme {
+            args.theme = theme;
+        }
+        if let Some(boot_mode) = self.boot_mode {
+            args.boot_mode = boot_mode;
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! RSPI-BIOS local displays
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use embedded_graphics::{pixelcolor::Rgb888, prelude::*};
+use tokio::io::AsyncWriteExt as _;
+
+use crate::api::{ApiSnapshot, SystemInfo};
+use crate::screen::{self, Palette};
+use crate::tui;
+use crate::AppState;
+
+/// CLI parameters for drawing the health screen on displays attached to the Pi, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct ConsoleArgs {
+    /// TTY or serial port to draw the health screen on with ANSI escape codes e.g. `/dev/tty1` or `/dev/ttyS0`,
+    /// the baud rate is left as configured (kernel command line, `stty`)
+    #[arg(long)]
+    pub(crate) console_tty: Option<PathBuf>,
+
+    /// Linux framebuffer device to draw the health screen on e.g. `/dev/fb0`
+    #[arg(long)]
+    pub(crate) framebuffer: Option<PathBuf>,
+
+    /// Framebuffer resolution as `<width>x<height>`, read from `/sys/class/graphics/` if not set e.g. when
+    /// drawing to a regular file
+    #[arg(long, value_parser = parse_size)]
+    framebuffer_size: Option<(u32, u32)>,
+
+    /// Framebuffer bits per pixel, `16` (RGB565) or `32` (XRGB8888), read from `/sys/class/graphics/` if not set
+    #[arg(long)]
+    framebuffer_bpp: Option<u32>,
+}
+
+impl ConsoleArgs {
+    pub(crate) const fn enabled(&self) -> bool {
+        self.console_tty.is_some() || self.framebuffer.is_some()
+    }
+}
+
+/// Parses `--framebuffer-size` e.g. `800x480`.
+fn parse_size(arg: &str) -> Result<(u32, u32), String> {
+    let (width, height) = arg
+        .split_once('x')
+        .ok_or_else(|| "expected <width>x<height>".to_string())?;
+    Ok((
+        width.parse().map_err(|e| format!("invalid width: {e}"))?,
+        height.parse().map_err(|e| format!("invalid height: {e}"))?,
+    ))
+}
+
+/// In-memory copy of a framebuffer, written to the device after every frame so it is never half drawn.
+struct Framebuffer {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    stride: usize,
+    bytes_per_pixel: usize,
+    buffer: Vec<u8>,
+}
+
+/// Reads a value from `/sys/class/graphics/<device>/`.
+async fn sysfs_value(device: &Path, name: &str) -> Option<String> {
+    let path = Path::new("/sys/class/graphics")
+        .join(device.file_name()?)
+        .join(name);
+    tokio::fs::read_to_string(path)
+        .await
+        .ok()
+        .map(|v| v.trim().to_string())
+}
+
+impl Framebuffer {
+    /// Uses `--framebuffer-size` and `--framebuffer-bpp` if set, the kernel's values otherwise.
+    async fn new(path: &Path, args: &ConsoleArgs) -> Result<Self, String> {
+        let (width, height) = match args.framebuffer_size {
+            Some(size) => size,
+            // e.g. `1920,1080`
+            None => sysfs_value(path, "virtual_size")
+                .await
+                .and_then(|s| parse_size(&s.replace(',', "x")).ok())
+                .ok_or("unknown resolution, set --framebuffer-size")?,
+        };
+        let bits_per_pixel = match args.framebuffer_bpp {
+            Some(bpp) => bpp,
+            None => sysfs_value(path, "bits_per_pixel")
+                .await
+                .and_then(|s| s.parse().ok())
+                .ok_or("unknown bits per pixel, set --framebuffer-bpp")?,
+        };
+        if bits_per_pixel != 16 && bits_per_pixel != 32 {
+            return Err(format!("{bits_per_pixel} bits per pixel isn't supported"));
+        }
+
+        let bytes_per_pixel = usize::try_from(bits_per_pixel / 8).unwrap_or(4);
+        let row_bytes = usize::try_from(width).unwrap_or(0) * bytes_per_pixel;
+        // Rows can be padded, a size set by hand describes a packed file
+        let stride = if args.framebuffer_size.is_some() {
+            row_bytes
+        } else {
+            sysfs_value(path, "stride")
+                .await
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(row_bytes)
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            width,
+            height,
+            stride,
+            bytes_per_pixel,
+            buffer: vec![0; stride * usize::try_from(height).unwrap_or(0)],
+        })
+    }
+
+    async fn flush(&self) -> std::io::Result<()> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&self.buffer).await?;
+        file.flush().await
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let (Ok(x), Ok(y)) = (u32::try_from(point.x), u32::try_from(point.y)) else {
+                continue;
+            };
+            if x >= self.width || y >= self.height {
+                continue;
+            }
+
+            let offset = usize::try_from(y).unwrap_or(0) * self.stride
+                + usize::try_from(x).unwrap_or(0) * self.bytes_per_pixel;
+            let Some(pixel) = self.buffer.get_mut(offset..offset + self.bytes_per_pixel) else {
+                continue;
+            };
+            if let [low, high] = pixel {
+                // RGB565, little-endian
+                let rgb565 = (u16::from(color.r() >> 3) << 11)
+                    | (u16::from(color.g() >> 2) << 5)
+                    | u16::from(color.b() >> 3);
+                [*low, *high] = rgb565.to_le_bytes();
+            } else {
+                // XRGB8888, little-endian
+                pixel.copy_from_slice(&[color.b(), color.g(), color.r(), 0]);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the health screen to `--console-tty`, reopened every time so the device can come and go.
+async fn write_tty(path: &Path, screen: &str) -> std::io::Result<()> {
+    let mut tty = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+    tty.write_all(screen.as_bytes()).await?;
+    tty.flush().await
+}
+
+/// Logs the first error of a display and when it recovers, instead of every refresh.
+fn report(name: &Path, result: std::io::Result<()>, failing: &mut bool) {
+    match result {
+        Ok(()) if *failing => {
+            tracing::info!("Drawing to {} again", name.display());
+            *failing = false;
+        }
+        Err(e) if !*failing => {
+            tracing::error!(error = %e, "Failed to draw to {}", name.display());
+            *failing = true;
+        }
+        _ => {}
+    }
+}
+
+/// Draws the health screen to `--console-tty` and `--framebuffer` every time a new [`crate::SystemSnapshot`] is
+/// taken.
+pub(crate) async fn draw_system_messages(args: ConsoleArgs, state: Arc<AppState>) {
+    let mut framebuffer = match &args.framebuffer {
+        Some(path) => match Framebuffer::new(path, &args).await {
+            Ok(f) => {
+                tracing::info!(
+                    "Drawing to {} ({}x{}, {} bytes per pixel)",
+                    path.display(),
+                    f.width,
+                    f.height,
+                    f.bytes_per_pixel
+                );
+                Some(f)
+            }
+            Err(e) => {
+                tracing::error!("Framebuffer {} disabled. Error: {e}", path.display());
+                None
+            }
+        },
+        None => None,
+    };
+
+    let system: SystemInfo = SystemInfo::from_state(&state).await;
+    let mut snapshot_rx = state.snapshot_tx.subscribe();
+    let (mut tty_failing, mut framebuffer_failing) = (false, false);
+
+    while snapshot_rx.changed().await.is_ok() {
+        let current = ApiSnapshot {
+            system: system.clone(),
+            snapshot: snapshot_rx.borrow_and_update().clone(),
+        };
+        let status = screen::status_line(&system);
+
+        if let Some(path) = &args.console_tty {
+            let result = write_tty(path, &tui::health_screen(&current, &status)).await;
+            report(path, result, &mut tty_failing);
+        }
+        if let Some(mut fb) = framebuffer.take() {
+            let palette = Palette::for_theme(state.args.lock().await.theme);
+            // Drawing every pixel of up to 1920x1080 would hold up other tasks on this worker
+            let drawn = tokio::task::spawn_blocking(move || {
+                screen::draw_health_screen(&mut fb, &current, &status, &palette);
+                fb
+            })
+            .await;
+            match drawn {
+                Ok(fb) => {
+                    let result = fb.flush().await;
+                    report(&fb.path, result, &mut framebuffer_failing);
+                    framebuffer = Some(fb);
+                }
+                Err(e) => tracing::error!(error = %e, "Framebuffer disabled, failed to draw"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packed `4x3` framebuffer backed by a zeroed file of the right size, removed once dropped.
+    struct TempFramebuffer(Framebuffer);
+
+    impl TempFramebuffer {
+        async fn new(bits_per_pixel: u32) -> Result<Self, String> {
+            let path = std::env::temp_dir().join(format!(
+                "rspi-bios-fb{bits_per_pixel}-{}",
+                std::process::id()
+            ));
+            let len = 4 * 3 * usize::try_from(bits_per_pixel / 8).map_err(|e| e.to_string())?;
+            tokio::fs::write(&path, vec![0; len])
+                .await
+                .map_err(|e| e.to_string())?;
+
+            let args = ConsoleArgs {
+                console_tty: None,
+                framebuffer: Some(path.clone()),
+                framebuffer_size: Some((4, 3)),
+                framebuffer_bpp: Some(bits_per_pixel),
+            };
+            let framebuffer = Framebuffer::new(&path, &args).await;
+            if framebuffer.is_err() {
+                let _ = std::fs::remove_file(&path);
+            }
+            Ok(Self(framebuffer?))
+        }
+
+        /// Flushes the drawn frame and reads the file back.
+        async fn bytes(&self) -> Result<Vec<u8>, String> {
+            self.0.flush().await.map_err(|e| e.to_string())?;
+            tokio::fs::read(&self.0.path)
+                .await
+                .map_err(|e| e.to_string())
+        }
+    }
+
+    impl Drop for TempFramebuffer {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0.path);
+        }
+    }
+
+    #[tokio::test]
+    async fn draws_rgb565() -> Result<(), String> {
+        let mut fb = TempFramebuffer::new(16).await?;
+        let Ok(()) = [
+            Pixel(Point::new(1, 0), Rgb888::new(0xff, 0, 0)),
+            Pixel(Point::new(3, 2), Rgb888::new(0x12, 0x34, 0x56)),
+            // Outside of the screen
+            Pixel(Point::new(4, 0), Rgb888::WHITE),
+            Pixel(Point::new(-1, 0), Rgb888::WHITE),
+        ]
+        .into_iter()
+        .draw(&mut fb.0);
+
+        let mut expected = vec![0; 4 * 3 * 2];
+        // Row 0, column 1
+        expected.splice(2..4, [0x00, 0xf8]);
+        // Row 2 (2 * 8 bytes), column 3 (3 * 2 bytes)
+        expected.splice(22..24, [0xaa, 0x11]);
+        assert_eq!(fb.bytes().await?, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn draws_xrgb8888() -> Result<(), String> {
+        let mut fb = TempFramebuffer::new(32).await?;
+        let Ok(()) = [
+            Pixel(Point::new(0, 0), Rgb888::WHITE),
+            Pixel(Point::new(2, 1), Rgb888::new(0x12, 0x34, 0x56)),
+            Pixel(Point::new(0, 3), Rgb888::WHITE),
+        ]
+        .into_iter()
+        .draw(&mut fb.0);
+
+        let mut expected = vec![0; 4 * 3 * 4];
+        expected.splice(0..4, [0xff, 0xff, 0xff, 0x00]);
+        // Row 1 (1 * 16 bytes), column 2 (2 * 4 bytes)
+        expected.splice(24..28, [0x56, 0x34, 0x12, 0x00]);
+        assert_eq!(fb.bytes().await?, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_unsupported_bpp() {
+        assert!(TempFramebuffer::new(24).await.is_err());
+    }
+}
@@ -0,0 +1,573 @@
+//! RSPI-BIOS Docker/Podman container status
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::HeaderMap,
+    response::Response,
+    Extension, Json,
+};
+use bytes::Bytes;
+use http_body_util::{BodyExt as _, Empty};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::security::CspNonce;
+use crate::snapshot::SystemSnapshot;
+use crate::theme::SelectedTheme;
+use crate::AppState;
+
+/// CLI parameters for the container listing, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct ContainersArgs {
+    /// Docker or Podman API socket to list containers from e.g. `/var/run/docker.sock`, `/run/podman/podman.sock`
+    #[arg(long)]
+    container_socket: Option<PathBuf>,
+}
+
+impl ContainersArgs {
+    pub(crate) const fn enabled(&self) -> bool {
+        self.container_socket.is_some()
+    }
+}
+
+/// State of a container, sent in `containers` SSE events and by `GET /api/containers`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct ContainerStatus {
+    /// Short ID, 12 characters like `docker ps`
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) image: String,
+    /// e.g. `running`, `exited`, `restarting`
+    pub(crate) state: String,
+    /// `healthy`, `unhealthy` or `starting`, [`None`] without a health check
+    pub(crate) health: Option<String>,
+    pub(crate) restarts: u32,
+    /// Seconds since the container started, [`None`] if it isn't running
+    pub(crate) uptime: Option<u64>,
+    /// CPU usage in percent, 100 per core like `docker stats`. [`None`] until the second sample
+    pub(crate) cpu_usage: Option<f32>,
+    /// Memory used without the page cache in bytes, like `docker stats`
+    pub(crate) memory_usage: Option<u64>,
+    pub(crate) memory_limit: Option<u64>,
+}
+
+impl ContainerStatus {
+    /// Shown in the listing, `-` if it isn't running.
+    pub(crate) fn uptime_text(&self) -> String {
+        self.uptime.map_or_else(
+            || "-".to_string(),
+            |u| format!("{}h {:02}m", u / 3_600, u / 60 % 60),
+        )
+    }
+
+    pub(crate) fn cpu_text(&self) -> String {
+        self.cpu_usage
+            .map_or_else(|| "-".to_string(), |c| format!("{c:.1}%"))
+    }
+
+    pub(crate) fn memory_text(&self) -> String {
+        self.memory_usage
+            .map_or_else(|| "-".to_string(), |m| format!("{}MB", m / 1_048_576))
+    }
+}
+
+/// Item of `GET /containers/json`.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedContainer {
+    id: String,
+    #[serde(default)]
+    names: Vec<String>,
+    #[serde(default)]
+    image: String,
+    #[serde(default)]
+    state: String,
+}
+
+/// Part of `GET /containers/{id}/json`.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedContainer {
+    #[serde(default)]
+    restart_count: u32,
+    state: InspectedState,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedState {
+    /// RFC 3339, `0001-01-01T00:00:00Z` if it never started
+    #[serde(default)]
+    started_at: String,
+    health: Option<InspectedHealth>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedHealth {
+    status: String,
+}
+
+/// Part of `GET /containers/{id}/stats`.
+#[derive(Deserialize)]
+struct Stats {
+    cpu_stats: CpuStats,
+    memory_stats: MemoryStats,
+}
+
+#[derive(Deserialize)]
+struct CpuStats {
+    cpu_usage: CpuUsage,
+    /// Missing on some platforms e.g. Windows
+    system_cpu_usage: Option<u64>,
+    online_cpus: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct CpuUsage {
+    total_usage: u64,
+}
+
+#[derive(Deserialize)]
+struct MemoryStats {
+    usage: Option<u64>,
+    limit: Option<u64>,
+    #[serde(default)]
+    stats: HashMap<String, u64>,
+}
+
+impl MemoryStats {
+    /// Subtracts the page cache like `docker stats`, `inactive_file` on cgroup v2 and `total_inactive_file` on v1.
+    fn used(&self) -> Option<u64> {
+        let cache = self
+            .stats
+            .get("inactive_file")
+            .or_else(|| self.stats.get("total_inactive_file"))
+            .copied()
+            .unwrap_or(0);
+        self.usage.map(|u| u.saturating_sub(cache))
+    }
+}
+
+/// Connection to the Docker/Podman API, both serve the same endpoints over HTTP/1.1.
+struct Engine {
+    sender: hyper::client::conn::http1::SendRequest<Empty<Bytes>>,
+}
+
+impl Engine {
+    #[cfg(unix)]
+    async fn connect(socket: &Path) -> Result<Self, String> {
+        let stream = tokio::net::UnixStream::connect(socket)
+            .await
+            .map_err(|e| format!("Failed to connect to {}. Error: {e}", socket.display()))?;
+        let (sender, connection) =
+            hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+                .await
+                .map_err(|e| e.to_string())?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::debug!(error = %e, "Container engine connection closed");
+            }
+        });
+        Ok(Self { sender })
+    }
+
+    #[cfg(not(unix))]
+    async fn connect(_socket: &Path) -> Result<Self, String> {
+        Err("unix sockets are not supported on this platform".to_string())
+    }
+
+    async fn get<T: DeserializeOwned>(&mut self, path: &str) -> Result<T, String> {
+        self.get_if_found(path)
+            .await?
+            .ok_or_else(|| format!("404 Not Found from {path}"))
+    }
+
+    /// Returns [`None`] on `404 Not Found` e.g. for a container removed since it was listed.
+    async fn get_if_found<T: DeserializeOwned>(&mut self, path: &str) -> Result<Option<T>, String> {
+        let request = axum::http::Request::get(path)
+            .header(axum::http::header::HOST, "localhost")
+            .body(Empty::new())
+            .map_err(|e| e.to_string())?;
+        self.sender.ready().await.map_err(|e| e.to_string())?;
+        let response = self
+            .sender
+            .send_request(request)
+            .await
+            .map_err(|e| e.to_string())?;
+        let status_code = response.status();
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(|e| e.to_string())?
+            .to_bytes();
+        if status_code == axum::http::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status_code.is_success() {
+            return Err(format!("{status_code} from {path}"));
+        }
+        serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|e| format!("Invalid response from {path}. Error: {e}"))
+    }
+}
+
+/// Previous `(container CPU time, system CPU time)` of every container, needed for the CPU usage.
+type CpuSamples = HashMap<String, (u64, u64)>;
+
+fn cpu_usage(stats: &CpuStats, previous: Option<(u64, u64)>) -> Option<f32> {
+    let (previous_total, previous_system) = previous?;
+    let container = stats.cpu_usage.total_usage.checked_sub(previous_total)?;
+    let system = stats.system_cpu_usage?.checked_sub(previous_system)?;
+    if system == 0 {
+        return None;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    Some(container as f32 / system as f32 * stats.online_cpus.unwrap_or(1) as f32 * 100.0)
+}
+
+/// Seconds since `started_at` (RFC 3339), [`None`] if it never started.
+fn uptime(started_at: &str) -> Option<u64> {
+    let started = OffsetDateTime::parse(started_at, &time::format_description::well_known::Rfc3339)
+        .ok()?
+        .unix_timestamp();
+    let started = u64::try_from(started).ok().filter(|&s| s > 0)?;
+    Some(SystemSnapshot::now().saturating_sub(started))
+}
+
+/// Returns [`None`] if the container was removed since it was listed.
+async fn container_status(
+    engine: &mut Engine,
+    listed: ListedContainer,
+    samples: &mut CpuSamples,
+) -> Result<Option<ContainerStatus>, String> {
+    let Some(inspected) = engine
+        .get_if_found::<InspectedContainer>(&format!("/containers/{}/json", listed.id))
+        .await?
+    else {
+        return Ok(None);
+    };
+    let running = listed.state == "running";
+
+    let (cpu_usage, memory_usage, memory_limit) = if running {
+        // Without `one-shot` the engine waits a second to sample the CPU usage itself
+        let Some(stats) = engine
+            .get_if_found::<Stats>(&format!(
+                "/containers/{}/stats?stream=false&one-shot=true",
+                listed.id
+            ))
+            .await?
+        else {
+            return Ok(None);
+        };
+        let previous = samples.insert(
+            listed.id.clone(),
+            (
+                stats.cpu_stats.cpu_usage.total_usage,
+                stats.cpu_stats.system_cpu_usage.unwrap_or(0),
+            ),
+        );
+        (
+            cpu_usage(&stats.cpu_stats, previous),
+            stats.memory_stats.used(),
+            stats.memory_stats.limit,
+        )
+    } else {
+        samples.remove(&listed.id);
+        (None, None, None)
+    };
+
+    Ok(Some(ContainerStatus {
+        id: listed.id.chars().take(12).collect(),
+        name: listed
+            .names
+            .first()
+            .map(|n| n.trim_start_matches('/').to_string())
+            .unwrap_or_default(),
+        image: listed.image,
+        health: inspected.state.health.map(|h| h.status),
+        restarts: inspected.restart_count,
+        uptime: if running {
+            uptime(&inspected.state.started_at)
+        } else {
+            None
+        },
+        state: listed.state,
+        cpu_usage,
+        memory_usage,
+        memory_limit,
+    }))
+}
+
+/// Lists every container, including stopped ones.
+async fn collect(socket: &Path, samples: &mut CpuSamples) -> Result<Vec<ContainerStatus>, String> {
+    let mut engine = Engine::connect(socket).await?;
+    let listed: Vec<ListedContainer> = engine.get("/containers/json?all=true").await?;
+
+    let mut containers = Vec::with_capacity(listed.len());
+    for container in listed {
+        if let Some(status) = container_status(&mut engine, container, samples).await? {
+            containers.push(status);
+        }
+    }
+    // Removed containers
+    samples.retain(|id, _| containers.iter().any(|c| id.starts_with(&c.id)));
+    Ok(containers)
+}
+
+/// Asks the container engine for every container once every `system_refresh_interval` and sends them over
+/// `AppState::containers_tx` when they change.
+pub(crate) async fn watch_containers(args: ContainersArgs, state: Arc<AppState>) {
+    let Some(socket) = args.container_socket else {
+        return;
+    };
+    tracing::info!("Listing containers from {}", socket.display());
+
+    let mut samples = CpuSamples::new();
+    let mut failing = false;
+    loop {
+        match collect(&socket, &mut samples).await {
+            Ok(containers) => {
+                if failing {
+                    tracing::info!("Container engine is reachable again");
+                    failing = false;
+                }
+                state.containers_tx.send_if_modified(|current| {
+                    let changed = *current != containers;
+                    *current = containers;
+                    changed
+                });
+            }
+            Err(e) => {
+                // Logged once instead of every interval
+                if !failing {
+                    tracing::error!(error = %e, "Failed to list containers");
+                    failing = true;
+                }
+            }
+        }
+
+        let system_refresh_interval = state.args.lock().await.system_refresh_interval;
+        tokio::time::sleep(system_refresh_interval).await;
+    }
+}
+
+/// Describes data used in the `<theme>/containers.html` [`askama`] templates.
+pub(crate) struct ContainersData {
+    pub(crate) csp_nonce: String,
+    /// `false` unless `--container-socket` is set
+    pub(crate) enabled: bool,
+    pub(crate) containers: Vec<ContainerStatus>,
+}
+
+/// Query parameters of `GET /containers`.
+#[derive(Deserialize)]
+pub(crate) struct ContainersQuery {
+    theme: Option<String>,
+}
+
+/// Handles `GET` requests to `/containers`.
+pub(crate) async fn containers_handler(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    csp_nonce: Option<Extension<CspNonce>>,
+    Query(query): Query<ContainersQuery>,
+    headers: HeaderMap,
+    state: State<Arc<AppState>>,
+) -> Response {
+    tracing::info!("Connection made to containers from {addr}");
+
+    let containers = state.containers_tx.borrow().clone();
+    let (enabled, default_theme) = {
+        let args = state.args.lock().await;
+        (args.containers.enabled(), args.theme)
+    };
+    let data = ContainersData {
+        csp_nonce: csp_nonce.map(|Extension(n)| n.0).unwrap_or_default(),
+        enabled,
+        containers,
+    };
+
+    SelectedTheme::select(query.theme.as_deref(), &headers, default_theme).render_containers(&data)
+}
+
+/// Handles `GET` requests to `/api/containers`, returns the latest [`ContainerStatus`] of every container.
+pub(crate) async fn api_containers_handler(
+    state: State<Arc<AppState>>,
+) -> Json<Vec<ContainerStatus>> {
+    Json(state.containers_tx.borrow().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use axum::{extract::Path as UrlPath, routing::get, Router};
+    use serde_json::{json, Value};
+    use time::format_description::well_known::Rfc3339;
+
+    use super::*;
+
+    const WEB: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+    const DB: &str = "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210";
+    /// Listed but removed before it is inspected
+    const GONE: &str = "00000000000000000000000000000000000000000000000000000000000000ff";
+
+    const MIB: u64 = 1_048_576;
+
+    /// Canned Docker API serving `WEB` (running), `DB` (never started) and `GONE`, every `/stats` request adds
+    /// 0.5s of container CPU time and 10s of system CPU time.
+    fn engine(started_at: String) -> Router {
+        let samples = Arc::new(AtomicU64::new(0));
+        Router::new()
+            .route(
+                "/containers/json",
+                get(|| async {
+                    Json(json!([
+                        { "Id": WEB, "Names": ["/web"], "Image": "nginx", "State": "running" },
+                        { "Id": DB, "Names": ["/db"], "Image": "postgres", "State": "created" },
+                        { "Id": GONE, "Names": ["/gone"], "Image": "busybox", "State": "running" },
+                    ]))
+                }),
+            )
+            .route(
+                "/containers/:id/json",
+                get(|UrlPath(id): UrlPath<String>| async move {
+                    let state = match id.as_str() {
+                        WEB => {
+                            json!({ "StartedAt": started_at, "Health": { "Status": "healthy" } })
+                        }
+                        DB => json!({ "StartedAt": "0001-01-01T00:00:00Z" }),
+                        _ => return Err(axum::http::StatusCode::NOT_FOUND),
+                    };
+                    Ok(Json(json!({ "RestartCount": 2, "State": state })))
+                }),
+            )
+            .route(
+                "/containers/:id/stats",
+                get(move |UrlPath(id): UrlPath<String>| async move {
+                    if id != WEB {
+                        return Err(axum::http::StatusCode::NOT_FOUND);
+                    }
+                    let n = samples.fetch_add(1, Ordering::Relaxed) + 1;
+                    Ok(Json(json!({
+                        "cpu_stats": {
+                            "cpu_usage": { "total_usage": n * 500_000_000 },
+                            "system_cpu_usage": n * 10_000_000_000,
+                            "online_cpus": 4,
+                        },
+                        "memory_stats": {
+                            "usage": 100 * MIB,
+                            "limit": 1024 * MIB,
+                            "stats": { "inactive_file": 30 * MIB },
+                        },
+                    })))
+                }),
+            )
+    }
+
+    /// Serves `router` on a unix socket in the temp directory, removed once dropped.
+    struct StubEngine(PathBuf);
+
+    impl StubEngine {
+        fn serve(router: Router) -> Result<Self, String> {
+            use hyper_util::rt::{TokioExecutor, TokioIo};
+            use hyper_util::server::conn::auto;
+            use hyper_util::service::TowerToHyperService;
+
+            let path =
+                std::env::temp_dir().join(format!("rspi-bios-engine-{}.sock", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let listener = tokio::net::UnixListener::bind(&path).map_err(|e| e.to_string())?;
+
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let service = TowerToHyperService::new(router.clone());
+                    tokio::spawn(
+                        auto::Builder::new(TokioExecutor::new())
+                            .serve_connection(TokioIo::new(stream), service)
+                            .into_owned(),
+                    );
+                }
+            });
+            Ok(Self(path))
+        }
+    }
+
+    impl Drop for StubEngine {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn collects_containers() -> Result<(), String> {
+        let started_at = (OffsetDateTime::now_utc() - time::Duration::hours(1))
+            .format(&Rfc3339)
+            .map_err(|e| e.to_string())?;
+        let stub = StubEngine::serve(engine(started_at))?;
+        let mut samples = CpuSamples::new();
+
+        let containers = collect(&stub.0, &mut samples).await?;
+        let [web, db] = containers.as_slice() else {
+            return Err(format!("expected web and db, got {containers:?}"));
+        };
+
+        assert_eq!(web.id, "0123456789ab");
+        assert_eq!(web.name, "web");
+        assert_eq!(web.health.as_deref(), Some("healthy"));
+        assert_eq!(web.restarts, 2);
+        assert!(web.uptime.is_some_and(|u| (3_600..3_660).contains(&u)));
+        // Needs a previous sample
+        assert_eq!(web.cpu_usage, None);
+        assert_eq!(web.memory_usage, Some(70 * MIB));
+        assert_eq!(web.memory_limit, Some(1024 * MIB));
+
+        assert_eq!(db.name, "db");
+        assert_eq!(db.state, "created");
+        assert_eq!(db.health, None);
+        assert_eq!(db.uptime, None);
+        assert_eq!(db.cpu_usage, None);
+        assert_eq!(db.memory_usage, None);
+        assert_eq!(samples.len(), 1);
+
+        // 0.5s of 10s on 4 CPUs
+        let containers = collect(&stub.0, &mut samples).await?;
+        let cpu_usage = containers.first().and_then(|c| c.cpu_usage);
+        assert!(
+            cpu_usage.is_some_and(|c| (c - 20.0).abs() < 0.01),
+            "{cpu_usage:?}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn uptime_of_a_container_that_never_started() {
+        assert_eq!(uptime("0001-01-01T00:00:00Z"), None);
+        assert_eq!(uptime(""), None);
+    }
+
+    #[test]
+    fn memory_used_without_page_cache() -> Result<(), String> {
+        let parse =
+            |value: Value| serde_json::from_value::<MemoryStats>(value).map_err(|e| e.to_string());
+
+        let v2 = parse(json!({ "usage": 100, "stats": { "inactive_file": 30 } }))?;
+        assert_eq!(v2.used(), Some(70));
+        let v1 = parse(json!({ "usage": 100, "stats": { "total_inactive_file": 40 } }))?;
+        assert_eq!(v1.used(), Some(60));
+        let no_cache = parse(json!({ "usage": 100 }))?;
+        assert_eq!(no_cache.used(), Some(100));
+        let stopped = parse(json!({}))?;
+        assert_eq!(stopped.used(), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+//! RSPI-BIOS health and readiness endpoints
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+
+use crate::snapshot::SystemSnapshot;
+use crate::AppState;
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Checks {
+    collector: Check,
+    tls: Check,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    checks: Checks,
+}
+
+/// Handles `GET` requests to `/healthz`.
+///
+/// Only checks that the process is responsive, doesn't touch any locks.
+pub(crate) async fn healthz_handler() -> impl IntoResponse {
+    Json(Health {
+        status: "ok",
+        version: env!("RSPI_BIOS_VERSION"),
+    })
+}
+
+/// Handles `GET` requests to `/readyz`.
+///
+/// Returns `503 Service Unavailable` unless `send_system_messages()` produced a snapshot within the last
+/// `readiness_max_missed_intervals` refresh intervals and TLS is loaded.
+pub(crate) async fn readyz_handler(state: State<Arc<AppState>>) -> impl IntoResponse {
+    // The refresh interval can be changed in SETUP
+    let max_snapshot_age = {
+        let args = state.args.lock().await;
+        args.system_refresh_interval * args.readiness_max_missed_intervals
+    };
+    let collector = collector_check(state.snapshot_tx.borrow().timestamp, max_snapshot_age);
+    let tls = Check {
+        ok: state.tls_loaded,
+        detail: None,
+    };
+
+    let ready = collector.ok && tls.ok;
+    let status_code = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status_code,
+        Json(Readiness {
+            status: if ready { "ready" } else { "not_ready" },
+            checks: Checks { collector, tls },
+        }),
+    )
+}
+
+fn collector_check(snapshot_timestamp: u64, max_age: Duration) -> Check {
+    if snapshot_timestamp == 0 {
+        return Check {
+            ok: false,
+            detail: Some("no snapshot collected yet".to_string()),
+        };
+    }
+
+    let age = Duration::from_secs(SystemSnapshot::now().saturating_sub(snapshot_timestamp));
+    Check {
+        ok: age <= max_age,
+        detail: Some(format!("last snapshot {}s ago", age.as_secs())),
+    }
+}
@@ -0,0 +1,502 @@
+//! RSPI-BIOS fleet hub
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{ConnectInfo, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive},
+        IntoResponse, Response, Sse,
+    },
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt as _};
+
+use crate::admin::{read_token, token_matches};
+use crate::api::{ApiSnapshot, SystemInfo};
+use crate::client::build_client;
+use crate::security::CspNonce;
+use crate::snapshot::SystemSnapshot;
+use crate::theme::SelectedTheme;
+use crate::tui::HealthLines;
+use crate::AppState;
+
+/// Nodes are shown offline after this many poll intervals without a snapshot.
+const MAX_MISSED_POLLS: u32 = 3;
+
+/// CLI parameters for aggregating other instances on one dashboard, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct HubArgs {
+    /// Serve `/hub`, a boot menu listing every node. Implied by `--hub-peer`
+    #[arg(long)]
+    hub: bool,
+
+    /// Base URL of an instance polled in hub mode, can be repeated e.g. `https://pi-attic.local:3000`
+    #[arg(long)]
+    hub_peer: Vec<reqwest::Url>,
+
+    /// How often `/api/snapshot` of every peer is requested
+    #[arg(long, value_parser = crate::parse_duration, default_value = "5")]
+    hub_poll_interval: Duration,
+
+    /// PEM certificate to trust for peers in addition to the system roots e.g. a shared self-signed `cert.pem`
+    #[arg(long)]
+    hub_ca_file: Option<PathBuf>,
+
+    /// Don't verify peer certificates
+    #[arg(long)]
+    hub_insecure: bool,
+
+    /// PEM file with a certificate and its private key, presented to peers that require a client certificate
+    #[arg(long)]
+    hub_client_cert: Option<PathBuf>,
+
+    /// File containing the token agents send to push snapshots (`--agent-token-file` on the agents), enables
+    /// `POST /hub/push`
+    #[arg(long)]
+    hub_agent_token_file: Option<PathBuf>,
+}
+
+impl HubArgs {
+    pub(crate) fn enabled(&self) -> bool {
+        self.hub || !self.hub_peer.is_empty() || self.hub_agent_token_file.is_some()
+    }
+}
+
+/// Body of `POST /hub/push` requests, sent by `--agent-hub-url`.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct AgentPush {
+    /// Node ID on the hub
+    pub(crate) name: String,
+    pub(crate) system: SystemInfo,
+    /// Oldest first, more than one if snapshots were buffered while the hub was unreachable
+    pub(crate) snapshots: Vec<SystemSnapshot>,
+}
+
+/// Latest information about a node.
+struct Node {
+    /// Dashboard of the node, [`None`] for agents since the hub can't reach them
+    url: Option<reqwest::Url>,
+    current: Option<ApiSnapshot>,
+    last_seen: Option<Instant>,
+    /// Why the last poll failed or how long ago the last push was
+    error: Option<String>,
+    /// Snapshots replayed by the agent after its last outage and how many seconds they covered
+    last_outage: Option<(usize, u64)>,
+}
+
+/// A node as shown in `<theme>/hub.html` and sent as a `node` event by `/hub/sse`.
+#[derive(Clone, Serialize)]
+pub(crate) struct NodeView {
+    /// [`Hub`] key, the `host:port` of peers or the name of agents
+    pub(crate) id: String,
+    /// Host name reported by the node, the ID until it answered once
+    pub(crate) name: String,
+    pub(crate) model: String,
+    /// CPU, memory and uptime on one line
+    pub(crate) summary: String,
+    /// `Online`, `Offline` or `Waiting`
+    pub(crate) status: &'static str,
+    /// Error or age of the last snapshot
+    pub(crate) detail: String,
+    pub(crate) url: Option<String>,
+    /// Health screen text, empty until the node answered once
+    pub(crate) screen: String,
+}
+
+impl Node {
+    fn view(&self, id: &str, stale_after: Duration) -> NodeView {
+        let age = self.last_seen.map(|t| t.elapsed());
+        let status = match (&self.error, age) {
+            (Some(_), _) => "Offline",
+            (None, None) => "Waiting",
+            (None, Some(age)) if age > stale_after => "Offline",
+            (None, Some(_)) => "Online",
+        };
+        let detail = match (&self.error, age) {
+            (Some(e), _) => e.clone(),
+            (None, None) => "Waiting for the first snapshot".to_string(),
+            (None, Some(age)) => match self.last_outage {
+                Some((count, secs)) => format!(
+                    "Last snapshot {}s ago, replayed {count} snapshots after a {secs}s outage",
+                    age.as_secs()
+                ),
+                None => format!("Last snapshot {}s ago", age.as_secs()),
+            },
+        };
+
+        let (name, model, summary, screen) = self.current.as_ref().map_or_else(
+            || (id.to_string(), String::new(), String::new(), String::new()),
+            |current| {
+                let snapshot = &current.snapshot;
+                let lines = HealthLines::new(current);
+                (
+                    current.system.host_name.clone(),
+                    current.system.model_name.clone(),
+                    format!(
+                        "CPU {:.1}%  RAM {}/{}MB  Up {}h",
+                        snapshot.cpu_usage,
+                        snapshot.used_memory / 1_048_576,
+                        snapshot.total_memory / 1_048_576,
+                        snapshot.uptime / 3_600
+                    ),
+                    lines
+                        .header
+                        .iter()
+                        .chain(&lines.specs)
+                        .map(|line| line.trim_end())
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                )
+            },
+        );
+
+        NodeView {
+            id: id.to_string(),
+            name,
+            model,
+            summary,
+            status,
+            detail,
+            url: self.url.as_ref().map(ToString::to_string),
+            screen,
+        }
+    }
+}
+
+/// Latest snapshots of every node, shared by the pollers and the `/hub` routes.
+pub(crate) struct Hub {
+    client: reqwest::Client,
+    poll_interval: Duration,
+    /// Sorted by ID so the boot menu order is stable
+    nodes: Mutex<BTreeMap<String, Node>>,
+    /// Every change to a node, for `/hub/sse`
+    events_tx: broadcast::Sender<NodeView>,
+    /// `--hub-agent-token-file`, `POST /hub/push` is disabled if [`None`]
+    agent_token: Option<String>,
+}
+
+/// Key of a peer, its URL minus the scheme and path.
+fn peer_id(url: &reqwest::Url) -> String {
+    match (url.host_str(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        _ => url.to_string(),
+    }
+}
+
+impl Hub {
+    /// Starts polling every `--hub-peer` and watching for agents that stopped pushing.
+    pub(crate) async fn new(args: &HubArgs) -> Result<Arc<Self>, String> {
+        let agent_token = match &args.hub_agent_token_file {
+            Some(path) => Some(
+                read_token(path)
+                    .await
+                    .ok_or_else(|| format!("no agent token in {}", path.display()))?,
+            ),
+            None => None,
+        };
+        let hub = Arc::new(Self {
+            client: build_client(
+                args.hub_ca_file.as_deref(),
+                args.hub_client_cert.as_deref(),
+                args.hub_insecure,
+            )
+            .await?,
+            poll_interval: args.hub_poll_interval,
+            nodes: Mutex::new(BTreeMap::new()),
+            events_tx: broadcast::Sender::new(64),
+            agent_token,
+        });
+
+        for url in &args.hub_peer {
+            hub.add_peer(url.clone()).await;
+        }
+        if hub.agent_token.is_some() {
+            tokio::spawn(watch_agents(hub.clone()));
+        }
+        Ok(hub)
+    }
+
+    fn stale_after(&self) -> Duration {
+        self.poll_interval * MAX_MISSED_POLLS
+    }
+
+    /// Adds a node for `url` and polls it until shutdown, does nothing if it is already known.
+    pub(crate) async fn add_peer(self: &Arc<Self>, url: reqwest::Url) {
+        let id = peer_id(&url);
+        let endpoint = match url.join("api/snapshot") {
+            Ok(e) => e,
+            Err(e) => {
+                tracing::error!(error = %e, "Invalid hub peer URL {url}");
+                return;
+            }
+        };
+
+        {
+            let mut nodes = self.nodes.lock().await;
+            if nodes.contains_key(&id) {
+                return;
+            }
+            nodes.insert(
+                id.clone(),
+                Node {
+                    url: Some(url),
+                    current: None,
+                    last_seen: None,
+                    error: None,
+                    last_outage: None,
+                },
+            );
+        }
+        tracing::info!("Polling hub peer {id}");
+        tokio::spawn(poll_peer(self.clone(), id, endpoint));
+    }
+
+    /// Stores the result of a poll and tells `/hub/sse` clients about it.
+    async fn update(&self, id: &str, result: Result<ApiSnapshot, String>) {
+        let mut nodes = self.nodes.lock().await;
+        let Some(node) = nodes.get_mut(id) else {
+            return;
+        };
+        match result {
+            Ok(current) => {
+                if node.error.take().is_some() {
+                    tracing::info!("Hub node {id} is back online");
+                }
+                node.current = Some(current);
+                node.last_seen = Some(Instant::now());
+            }
+            Err(e) => {
+                // Logged once instead of every poll
+                if node.error.is_none() {
+                    tracing::warn!(error = %e, "Hub node {id} is offline");
+                }
+                node.error = Some(e);
+            }
+        }
+        let view = node.view(id, self.stale_after());
+        drop(nodes);
+
+        // No receivers if nobody has `/hub` open
+        let _ = self.events_tx.send(view);
+    }
+
+    /// Stores the newest snapshot of a push, adding the agent as a node the first time.
+    ///
+    /// Pushes with buffered snapshots are recorded as an outage spanning the oldest to the newest one, measured with
+    /// the agent's clock so skew between the two doesn't matter.
+    async fn receive(&self, push: AgentPush) {
+        let count = push.snapshots.len();
+        let oldest = push.snapshots.first().map(|s| s.timestamp);
+        let Some(snapshot) = push.snapshots.into_iter().last() else {
+            return;
+        };
+
+        let mut nodes = self.nodes.lock().await;
+        let node = nodes.entry(push.name.clone()).or_insert_with(|| {
+            tracing::info!("Agent {} joined the hub", push.name);
+            Node {
+                url: None,
+                current: None,
+                last_seen: None,
+                error: None,
+                last_outage: None,
+            }
+        });
+        if count > 1 {
+            let secs = oldest.map_or(0, |t| snapshot.timestamp.saturating_sub(t));
+            tracing::info!(
+                "Agent {} replayed {count} buffered snapshots after a {secs}s outage",
+                push.name
+            );
+            node.last_outage = Some((count, secs));
+        }
+        drop(nodes);
+        let current = ApiSnapshot {
+            system: push.system,
+            snapshot,
+        };
+        self.update(&push.name, Ok(current)).await;
+    }
+
+    pub(crate) async fn views(&self) -> Vec<NodeView> {
+        let stale_after = self.stale_after();
+        self.nodes
+            .lock()
+            .await
+            .iter()
+            .map(|(id, node)| node.view(id, stale_after))
+            .collect()
+    }
+}
+
+/// Requests `endpoint` (`/api/snapshot` of a peer) every `--hub-poll-interval`.
+async fn poll_peer(hub: Arc<Hub>, id: String, endpoint: reqwest::Url) {
+    let mut interval = tokio::time::interval(hub.poll_interval);
+    loop {
+        interval.tick().await;
+        let result = ApiSnapshot::fetch(&hub.client, &endpoint).await;
+        hub.update(&id, result).await;
+    }
+}
+
+/// Marks agents offline once they missed [`MAX_MISSED_POLLS`] poll intervals, they are only updated when they push.
+async fn watch_agents(hub: Arc<Hub>) {
+    let mut interval = tokio::time::interval(hub.poll_interval);
+    loop {
+        interval.tick().await;
+        let stale_after = hub.stale_after();
+        let stale: Vec<(String, Duration)> = hub
+            .nodes
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, node)| node.url.is_none() && node.error.is_none())
+            .filter_map(|(id, node)| {
+                let age = node.last_seen?.elapsed();
+                (age > stale_after).then(|| (id.clone(), age))
+            })
+            .collect();
+
+        for (id, age) in stale {
+            hub.update(&id, Err(format!("No push for {}s", age.as_secs())))
+                .await;
+        }
+    }
+}
+
+/// Describes data used in the `<theme>/hub.html` [`askama`] templates.
+pub(crate) struct HubData {
+    pub(crate) csp_nonce: String,
+    pub(crate) nodes: Vec<NodeView>,
+    /// Node picked with `?node=`, shown in the `#node` section
+    pub(crate) selected: Option<NodeView>,
+}
+
+/// Query parameters of `GET /hub`.
+#[derive(Deserialize)]
+struct HubQuery {
+    node: Option<String>,
+    theme: Option<String>,
+}
+
+/// Handles `GET` requests to `/hub`, the boot menu listing every node.
+async fn hub_handler(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    csp_nonce: Option<Extension<CspNonce>>,
+    Extension(hub): Extension<Arc<Hub>>,
+    Query(query): Query<HubQuery>,
+    headers: HeaderMap,
+    state: State<Arc<AppState>>,
+) -> Response {
+    tracing::info!("Connection made to hub from {addr}");
+
+    let nodes = hub.views().await;
+    let selected = query
+        .node
+        .and_then(|id| nodes.iter().find(|n| n.id == id).cloned());
+    let data = HubData {
+        csp_nonce: csp_nonce.map(|Extension(n)| n.0).unwrap_or_default(),
+        nodes,
+        selected,
+    };
+
+    let default_theme = state.args.lock().await.theme;
+    SelectedTheme::select(query.theme.as_deref(), &headers, default_theme).render_hub(&data)
+}
+
+/// Handles `GET` requests to `/hub/sse`, sends a `node` event every time a node is polled.
+async fn hub_sse_handler(
+    Extension(hub): Extension<Arc<Hub>>,
+    state: State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // Lagging clients skip the missed updates, the next poll brings them up to date
+    let stream = BroadcastStream::new(hub.events_tx.subscribe())
+        .filter_map(|view| Event::default().event("node").json_data(view.ok()?).ok())
+        .map(Ok);
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::new().interval(state.args.lock().await.sse_keep_alive_interval))
+}
+
+/// Handles `GET` requests to `/api/hub`, returns every node.
+async fn api_hub_handler(Extension(hub): Extension<Arc<Hub>>) -> Json<Vec<NodeView>> {
+    Json(hub.views().await)
+}
+
+/// Rejects pushes without `Authorization: Bearer <agent token>`.
+async fn require_agent_token(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(hub): State<Arc<Hub>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    let authorized = match (&hub.agent_token, provided) {
+        (Some(expected), Some(provided)) => token_matches(expected, provided),
+        _ => false,
+    };
+    if !authorized {
+        tracing::warn!("Rejected unauthorized hub push from {addr}");
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Handles `POST` requests to `/hub/push` from agents.
+///
+/// Returns `400 Bad Request` if the push has no name or snapshots.
+async fn push_handler(
+    Extension(hub): Extension<Arc<Hub>>,
+    Json(push): Json<AgentPush>,
+) -> Response {
+    if push.name.is_empty() || push.snapshots.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Push needs a name and snapshots").into_response();
+    }
+
+    hub.receive(push).await;
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Creates the `/hub` routes, only added in hub mode.
+pub(crate) fn hub_routes(hub: Arc<Hub>) -> Router<Arc<AppState>> {
+    let router = Router::new()
+        .route("/hub", get(hub_handler))
+        .route("/hub/sse", get(hub_sse_handler))
+        .route("/api/hub", get(api_hub_handler));
+    let router = if hub.agent_token.is_some() {
+        router.merge(
+            Router::new()
+                .route("/hub/push", post(push_handler))
+                .route_layer(middleware::from_fn_with_state(
+                    hub.clone(),
+                    require_agent_token,
+                )),
+        )
+    } else {
+        router
+    };
+    router.layer(Extension(hub))
+}
@@ -0,0 +1,453 @@
+//! RSPI-BIOS listeners
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{middleware::AddExtension, Extension, Router};
+use axum_server::accept::Accept;
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_rustls::server::TlsStream;
+use tower::Layer as _;
+
+use crate::mtls;
+use crate::systemd::{self, ActivatedSockets};
+
+/// What a listener serves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Purpose {
+    /// The BIOS dashboard, SSE stream and health checks
+    Dashboard,
+    /// Machine-readable endpoints e.g. `/metrics`
+    Metrics,
+    /// Redirects `HTTP` requests to the `HTTPS` dashboard
+    Redirect,
+}
+
+/// Where a listener accepts connections.
+#[derive(Clone, Debug)]
+pub(crate) enum Address {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    /// Socket passed in by systemd, identified by its `FileDescriptorName=`
+    Systemd(String),
+}
+
+/// A single listener definition, parsed from `--listen` e.g. `metrics=http://127.0.0.1:9100`.
+///
+/// Format: `[dashboard|metrics|redirect=](http|https)://(<ip>:<port>|unix:<path>|fd:<name>)`
+#[derive(Clone, Debug)]
+pub(crate) struct ListenerConfig {
+    pub(crate) purpose: Purpose,
+    pub(crate) tls: bool,
+    pub(crate) address: Address,
+}
+
+impl FromStr for Purpose {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dashboard" => Ok(Self::Dashboard),
+            "metrics" => Ok(Self::Metrics),
+            "redirect" => Ok(Self::Redirect),
+            _ => Err(format!(
+                "unknown purpose `{s}`, expected `dashboard`, `metrics` or `redirect`"
+            )),
+        }
+    }
+}
+
+impl FromStr for ListenerConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (purpose, url) = match s.split_once('=') {
+            Some((purpose, url)) => (purpose.parse()?, url),
+            None => (Purpose::Dashboard, s),
+        };
+
+        let tls = match url.split_once("://") {
+            Some(("https", _)) => true,
+            Some(("http", _)) => false,
+            _ => return Err(format!("expected `http://` or `https://`, got `{url}`")),
+        };
+        let address = url.split_once("://").map_or("", |(_, a)| a);
+
+        let address = if let Some(path) = address.strip_prefix("unix:") {
+            if tls {
+                return Err("unix sockets only support `http://`".to_string());
+            }
+            Address::Unix(PathBuf::from(path))
+        } else if let Some(name) = address.strip_prefix("fd:") {
+            Address::Systemd(name.to_string())
+        } else {
+            Address::Tcp(
+                address
+                    .parse()
+                    .map_err(|e| format!("invalid address `{address}`: {e}"))?,
+            )
+        };
+
+        Ok(Self {
+            purpose,
+            tls,
+            address,
+        })
+    }
+}
+
+impl fmt::Display for ListenerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls { "https" } else { "http" };
+        match &self.address {
+            Address::Tcp(addr) => write!(f, "{:?} on {scheme}://{addr}", self.purpose),
+            Address::Unix(path) => {
+                write!(
+                    f,
+                    "{:?} on {scheme}://unix:{}",
+                    self.purpose,
+                    path.display()
+                )
+            }
+            Address::Systemd(name) => write!(f, "{:?} on {scheme}://fd:{name}", self.purpose),
+        }
+    }
+}
+
+impl ListenerConfig {
+    pub(crate) const fn tcp_port(&self) -> Option<u16> {
+        match &self.address {
+            Address::Tcp(addr) => Some(addr.port()),
+            _ => None,
+        }
+    }
+}
+
+/// Request extension added to requests received over TLS e.g. to only send `Strict-Transport-Security` over `HTTPS`.
+#[derive(Clone, Debug)]
+pub(crate) struct TlsConnection {
+    /// Subject of the verified client certificate, [`None`] if the client didn't send one
+    pub(crate) client_subject: Option<Arc<str>>,
+}
+
+/// Wraps [`RustlsAcceptor`] to add a [`TlsConnection`] to every request on the connection once the handshake is done.
+///
+/// Based on example code from: <https://github.com/programatik29/axum-server/blob/v0.7.1/examples/rustls_session.rs>
+#[derive(Clone, Debug)]
+struct TlsConnectionAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl<I, S> Accept<I, S> for TlsConnectionAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = AddExtension<S, TlsConnection>;
+    type Future =
+        Pin<Box<dyn Future<Output = std::io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            let tls_connection = TlsConnection {
+                client_subject: mtls::client_subject(stream.get_ref().1),
+            };
+            if let Some(subject) = &tls_connection.client_subject {
+                tracing::debug!("Verified client certificate {subject}");
+            }
+
+            Ok((stream, Extension(tls_connection).layer(service)))
+        })
+    }
+}
+
+/// [`Router`] served for each [`Purpose`].
+pub(crate) struct Routers {
+    pub(crate) dashboard: Router,
+    pub(crate) metrics: Router,
+    pub(crate) redirect: Router,
+}
+
+impl Routers {
+    fn get(&self, purpose: Purpose) -> Router {
+        match purpose {
+            Purpose::Dashboard => self.dashboard.clone(),
+            Purpose::Metrics => self.metrics.clone(),
+            Purpose::Redirect => self.redirect.clone(),
+        }
+    }
+}
+
+enum BoundListener {
+    Tcp(std::net::TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+/// Binds a TCP listener.
+///
+/// IPv6 listeners are dual-stack unless an IPv4 listener uses the same port, in which case they are IPv6 only so both
+/// can bind.
+fn bind_tcp(addr: SocketAddr, only_v6: bool) -> std::io::Result<std::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &PathBuf) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // Remove stale socket left behind by a previous run, anything else is most likely a typo and left alone
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} exists and isn't a socket", path.display()),
+            ))
+        }
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+    tokio::net::UnixListener::bind(path)
+}
+
+fn bind(
+    config: &ListenerConfig,
+    configs: &[ListenerConfig],
+    activated_sockets: &mut ActivatedSockets,
+) -> std::io::Result<BoundListener> {
+    match &config.address {
+        Address::Tcp(addr) => {
+            let only_v6 = configs.iter().any(|c| {
+                matches!(c.address, Address::Tcp(other) if other.is_ipv4() && other.port() == addr.port())
+            });
+            bind_tcp(*addr, only_v6).map(BoundListener::Tcp)
+        }
+        #[cfg(unix)]
+        Address::Unix(path) => bind_unix(path).map(|l| BoundListener::Unix(l, path.clone())),
+        #[cfg(not(unix))]
+        Address::Unix(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "unix sockets are not supported on this platform",
+        )),
+        Address::Systemd(name) => activated_sockets
+            .take_tcp_listener(name)
+            .map(BoundListener::Tcp)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("no TCP socket named `{name}` received from systemd"),
+                )
+            }),
+    }
+}
+
+/// Binds every listener, then serves them all until they shut down.
+///
+/// TCP listeners share `handle`, unix socket listeners follow the same graceful shutdown via `shutdown_rx`.
+///
+/// Returns `false` if any listener fails to bind or serve e.g. if unable to bind to port.
+pub(crate) async fn serve_listeners(
+    configs: Vec<ListenerConfig>,
+    mut activated_sockets: ActivatedSockets,
+    routers: Routers,
+    tls_config: Option<RustlsConfig>,
+    handle: axum_server::Handle,
+    shutdown_rx: watch::Receiver<bool>,
+    graceful_shutdown_duration: Duration,
+) -> bool {
+    let mut bound = Vec::with_capacity(configs.len());
+    for config in &configs {
+        match bind(config, &configs, &mut activated_sockets) {
+            Ok(l) => bound.push(l),
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to bind {config}, did you set the correct permissions?");
+                return false;
+            }
+        }
+    }
+
+    let mut servers = JoinSet::new();
+    for (config, listener) in configs.into_iter().zip(bound) {
+        tracing::info!("Starting {config}");
+        let router = routers.get(config.purpose);
+
+        match listener {
+            BoundListener::Tcp(listener) => {
+                let tls_config = if config.tls {
+                    let Some(tls_config) = tls_config.clone() else {
+                        tracing::error!("No TLS config for {config}");
+                        return false;
+                    };
+                    Some(tls_config)
+                } else {
+                    None
+                };
+                servers.spawn(serve_tcp(
+                    config,
+                    listener,
+                    router,
+                    tls_config,
+                    handle.clone(),
+                ));
+            }
+            #[cfg(unix)]
+            BoundListener::Unix(listener, path) => {
+                servers.spawn(serve_unix(
+                    listener,
+                    path,
+                    router,
+                    shutdown_rx.clone(),
+                    graceful_shutdown_duration,
+                ));
+            }
+        }
+    }
+
+    systemd::notify_ready(&format!("Serving on {} listener(s)", servers.len()));
+
+    while let Some(result) = servers.join_next().await {
+        if !result.unwrap_or(false) {
+            servers.abort_all();
+            return false;
+        }
+    }
+
+    true
+}
+
+async fn serve_tcp(
+    config: ListenerConfig,
+    listener: std::net::TcpListener,
+    router: Router,
+    tls_config: Option<RustlsConfig>,
+    handle: axum_server::Handle,
+) -> bool {
+    let make_service = router.into_make_service_with_connect_info::<SocketAddr>();
+
+    let axum_result = match tls_config {
+        Some(tls_config) => {
+            axum_server::from_tcp(listener)
+                .acceptor(TlsConnectionAcceptor {
+                    inner: RustlsAcceptor::new(tls_config),
+                })
+                .handle(handle)
+                .serve(make_service)
+                .await
+        }
+        None => {
+            axum_server::from_tcp(listener)
+                .handle(handle)
+                .serve(make_service)
+                .await
+        }
+    };
+
+    if let Err(e) = axum_result {
+        tracing::error!(error = %e, "Failed to serve {config}");
+        return false;
+    }
+
+    true
+}
+
+/// Serves plain `HTTP` on a unix socket e.g. for a local reverse proxy.
+///
+/// [`axum_server`] only supports TCP so connections are driven by [`hyper_util`] directly.
+///
+/// Based on example code from: <https://github.com/tokio-rs/axum/blob/6efcb75d99a437fa80c81e2308ec8234b023e1a7/examples/unix-domain-socket/src/main.rs>
+#[cfg(unix)]
+async fn serve_unix(
+    listener: tokio::net::UnixListener,
+    path: PathBuf,
+    router: Router,
+    mut shutdown_rx: watch::Receiver<bool>,
+    graceful_shutdown_duration: Duration,
+) -> bool {
+    use axum::{extract::ConnectInfo, Extension};
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto;
+    use hyper_util::server::graceful::GracefulShutdown;
+    use hyper_util::service::TowerToHyperService;
+
+    // Unix socket peers don't have an IP address, handlers still expect one for logging
+    let router = router.layer(Extension(ConnectInfo(SocketAddr::from((
+        std::net::Ipv4Addr::UNSPECIFIED,
+        0,
+    )))));
+
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let graceful = GracefulShutdown::new();
+
+    loop {
+        let accept_failed = tokio::select! {
+            result = listener.accept() => match result {
+                Ok((stream, _)) => {
+                    let service = TowerToHyperService::new(router.clone());
+                    let connection = builder
+                        .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                        .into_owned();
+                    let connection = graceful.watch(connection);
+                    tokio::spawn(async move {
+                        if let Err(e) = connection.await {
+                            tracing::debug!(error = %e, "Unix socket connection error");
+                        }
+                    });
+                    false
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "Failed to accept unix socket connection");
+                    true
+                }
+            },
+            _ = shutdown_rx.wait_for(|shutdown| *shutdown) => break,
+        };
+
+        // Errors like EMFILE persist until connections close, retrying right away would spin. Same delay as
+        // `axum::serve`
+        if accept_failed {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+    }
+
+    drop(listener);
+    if let Err(e) = std::fs::remove_file(&path) {
+        tracing::debug!(error = %e, "Failed to remove {}", path.display());
+    }
+
+    if tokio::time::timeout(graceful_shutdown_duration, graceful.shutdown())
+        .await
+        .is_err()
+    {
+        tracing::debug!(
+            "Closing remaining unix socket connections on {}",
+            path.display()
+        );
+    }
+
+    true
+}
@@ -0,0 +1,141 @@
+//! RSPI-BIOS log files
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How often logs are rotated regardless of their size, periods start on UTC hour/day boundaries.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RotationPeriod {
+    Never,
+    Hourly,
+    Daily,
+}
+
+impl RotationPeriod {
+    /// Returns the index of the period `time` falls in, [`None`] for [`RotationPeriod::Never`].
+    fn period_of(self, time: SystemTime) -> Option<u64> {
+        let seconds = match self {
+            Self::Never => return None,
+            Self::Hourly => 60 * 60,
+            Self::Daily => 24 * 60 * 60,
+        };
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Some(since_epoch.as_secs() / seconds)
+    }
+}
+
+/// CLI parameters for rotating `debug.log` and `access.log`, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+#[allow(clippy::struct_field_names)]
+pub(crate) struct LogRotationArgs {
+    /// Rotate a log before it grows past this many bytes, `0` to disable
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    log_max_size: u64,
+
+    #[arg(long, value_enum, default_value_t = RotationPeriod::Daily)]
+    log_rotation: RotationPeriod,
+
+    /// Number of rotated files to keep per log e.g. `debug.log.1` to `debug.log.5`, `0` deletes logs when rotating
+    #[arg(long, default_value_t = 5)]
+    log_max_files: usize,
+}
+
+/// Log file that rotates itself by size and/or time so the SD card doesn't fill up.
+///
+/// Rotated files are renamed `<name>.1` (newest) up to `<name>.<log_max_files>` (oldest), anything older is
+/// removed.
+pub(crate) struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    period: Option<u64>,
+    args: LogRotationArgs,
+}
+
+fn open_append(path: &PathBuf) -> io::Result<File> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
+
+impl RotatingFile {
+    /// Opens or creates `path` in append mode.
+    ///
+    /// The current period is based on when the file was last modified, so restarting doesn't postpone rotation.
+    pub(crate) fn open(path: PathBuf, args: LogRotationArgs) -> io::Result<Self> {
+        let file = open_append(&path)?;
+        let metadata = file.metadata()?;
+        let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+
+        Ok(Self {
+            period: args.log_rotation.period_of(modified),
+            size: metadata.len(),
+            path,
+            file,
+            args,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+
+    fn should_rotate(&self, incoming: usize) -> bool {
+        let too_big = self.args.log_max_size > 0
+            && self.size > 0
+            && self.size + incoming as u64 > self.args.log_max_size;
+        let new_period = self.args.log_rotation.period_of(SystemTime::now()) != self.period;
+
+        too_big || new_period
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let ignore_not_found = |result: io::Result<()>| match result {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+
+        if self.args.log_max_files == 0 {
+            ignore_not_found(std::fs::remove_file(&self.path))?;
+        } else {
+            // Oldest is overwritten
+            for index in (1..self.args.log_max_files).rev() {
+                ignore_not_found(std::fs::rename(
+                    self.rotated_path(index),
+                    self.rotated_path(index + 1),
+                ))?;
+            }
+            ignore_not_found(std::fs::rename(&self.path, self.rotated_path(1)))?;
+        }
+
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        self.period = self.args.log_rotation.period_of(SystemTime::now());
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            if let Err(e) = self.rotate() {
+                // Can't use `tracing` since this may be the debug log, keep writing to the current file
+                eprintln!("Failed to rotate {}. Error: {e:?}", self.path.display());
+                self.size = 0;
+                self.period = self.args.log_rotation.period_of(SystemTime::now());
+            }
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
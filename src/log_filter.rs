@@ -0,0 +1,263 @@
+//! RSPI-BIOS runtime log filter
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::client::ClientArgs;
+use crate::AppState;
+
+/// Handle used to swap the [`EnvFilter`] applied to every log output.
+pub(crate) type ReloadHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Pending revert to the default filter.
+struct Revert {
+    task: JoinHandle<()>,
+    at: SystemTime,
+}
+
+/// Controls the active [`EnvFilter`], temporary filters revert back to the one used at startup.
+pub(crate) struct LogFilter {
+    handle: ReloadHandle,
+    default: String,
+    default_revert_after: Duration,
+    revert: Mutex<Option<Revert>>,
+}
+
+/// Body of `GET`/`PUT`/`DELETE` `/admin/log-filter` responses.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct LogFilterStatus {
+    filter: String,
+    default: String,
+    /// [`None`] if the filter won't be reverted
+    revert_in_secs: Option<u64>,
+}
+
+/// Body of `PUT /admin/log-filter` requests.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct SetLogFilter {
+    /// Directives in the `RUST_LOG` format e.g. `rspi_bios=trace,tower_http=info`
+    filter: String,
+    /// Uses `--log-filter-revert-after` if not set, `0` to never revert
+    revert_after_secs: Option<u64>,
+}
+
+impl LogFilter {
+    pub(crate) fn new(handle: ReloadHandle, default_revert_after: Duration) -> Self {
+        let default = handle.with_current(ToString::to_string).unwrap_or_default();
+        Self {
+            handle,
+            default,
+            default_revert_after,
+            revert: Mutex::new(None),
+        }
+    }
+
+    fn status(&self) -> LogFilterStatus {
+        let now = SystemTime::now();
+        let revert_in_secs = self.revert.lock().ok().and_then(|r| {
+            r.as_ref()
+                .and_then(|r| r.at.duration_since(now).ok())
+                .map(|d| d.as_secs())
+        });
+
+        LogFilterStatus {
+            filter: self
+                .handle
+                .with_current(ToString::to_string)
+                .unwrap_or_default(),
+            default: self.default.clone(),
+            revert_in_secs,
+        }
+    }
+
+    fn cancel_revert(&self) {
+        if let Ok(mut revert) = self.revert.lock() {
+            if let Some(r) = revert.take() {
+                r.task.abort();
+            }
+        }
+    }
+
+    /// Applies `directives`, reverting to the default filter after `revert_after` unless it is zero.
+    fn set(&self, directives: &str, revert_after: Option<Duration>) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+        self.handle.reload(filter).map_err(|e| e.to_string())?;
+        self.cancel_revert();
+
+        let revert_after = revert_after.unwrap_or(self.default_revert_after);
+        if revert_after.is_zero() {
+            return Ok(());
+        }
+
+        let handle = self.handle.clone();
+        let default = self.default.clone();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(revert_after).await;
+            match handle.reload(EnvFilter::new(&default)) {
+                Ok(()) => tracing::info!("Log filter reverted to `{default}`"),
+                Err(e) => tracing::error!(error = %e, "Failed to revert log filter"),
+            }
+        });
+        if let Ok(mut revert) = self.revert.lock() {
+            *revert = Some(Revert {
+                task,
+                at: SystemTime::now() + revert_after,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), String> {
+        self.cancel_revert();
+        self.handle
+            .reload(EnvFilter::new(&self.default))
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Handles `GET` requests to `/admin/log-filter`.
+pub(crate) async fn get_handler(state: State<Arc<AppState>>) -> Json<LogFilterStatus> {
+    Json(state.log_filter.status())
+}
+
+/// Handles `PUT` requests to `/admin/log-filter`.
+///
+/// Returns `400 Bad Request` if the directives are invalid.
+pub(crate) async fn put_handler(
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    state: State<Arc<AppState>>,
+    Json(body): Json<SetLogFilter>,
+) -> Response {
+    let revert_after = body.revert_after_secs.map(Duration::from_secs);
+    if let Err(e) = state.log_filter.set(&body.filter, revert_after) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    let status = state.log_filter.status();
+    tracing::info!(
+        revert_in_secs = status.revert_in_secs,
+        "Log filter changed to `{}` by {addr}",
+        status.filter
+    );
+    Json(status).into_response()
+}
+
+/// Handles `DELETE` requests to `/admin/log-filter`, reverts to the default filter.
+pub(crate) async fn delete_handler(
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    state: State<Arc<AppState>>,
+) -> Response {
+    if let Err(e) = state.log_filter.reset() {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+    }
+
+    tracing::info!("Log filter reset by {addr}");
+    Json(state.log_filter.status()).into_response()
+}
+
+/// `log-filter` subcommand, talks to the admin API of a running instance.
+#[derive(clap::Args, Debug)]
+pub(crate) struct LogFilterCommand {
+    /// Directives to apply e.g. `rspi_bios=trace,tower_http=debug`, prints the active filter if not set
+    directives: Option<String>,
+
+    /// Revert to the default filter after this long, `0` to never revert (defaults to the server's
+    /// `--log-filter-revert-after`)
+    #[arg(long, value_parser = crate::parse_duration)]
+    revert_after: Option<Duration>,
+
+    /// Revert to the default filter now
+    #[arg(long, conflicts_with_all = ["directives", "revert_after"])]
+    reset: bool,
+
+    /// File containing the admin token (`--admin-token-file`)
+    #[arg(long, default_value_os_t = PathBuf::from("/etc/rspi-bios/admin-token"))]
+    token_file: PathBuf,
+
+    #[command(flatten)]
+    client: ClientArgs,
+}
+
+/// Runs the `log-filter` subcommand, logging isn't set up so errors are printed to `STDERR`.
+pub(crate) async fn run_command(command: LogFilterCommand) -> ExitCode {
+    let token = match tokio::fs::read_to_string(&command.token_file).await {
+        Ok(t) => t.trim_end().to_string(),
+        Err(e) => {
+            eprintln!(
+                "Failed to read admin token from {}. Error: {e}",
+                command.token_file.display()
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (client, url) = match (
+        command.client.client().await,
+        command.client.endpoint("admin/log-filter"),
+    ) {
+        (Ok(client), Ok(url)) => (client, url),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let request = if command.reset {
+        client.delete(url)
+    } else if let Some(filter) = command.directives {
+        let body = SetLogFilter {
+            filter,
+            revert_after_secs: command.revert_after.map(|d| d.as_secs()),
+        };
+        client
+            .put(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&body).unwrap_or_default())
+    } else {
+        client.get(url)
+    };
+
+    let response = match request.bearer_auth(token).send().await {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Request failed. Error: {e:?}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let status_code = response.status();
+    let body = response.text().await.unwrap_or_default();
+    if !status_code.is_success() {
+        eprintln!("Request failed with {status_code}. {body}");
+        return ExitCode::FAILURE;
+    }
+
+    match serde_json::from_str::<LogFilterStatus>(&body) {
+        Ok(status) => {
+            println!("filter:  {}", status.filter);
+            println!("default: {}", status.default);
+            match status.revert_in_secs {
+                Some(secs) => println!("reverts in {secs}s"),
+                None => println!("doesn't revert"),
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Unexpected response. Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -2,42 +2,105 @@
 //! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
 //! SPDX-License-Identifier: GPL-3.0-only
 
+mod access_log;
+mod admin;
+mod agent;
+mod api;
+mod assets;
+mod boot;
+mod client;
+mod config;
+mod console;
+mod containers;
+mod health;
+mod hub;
+mod listeners;
+mod log_file;
+mod log_filter;
+mod mdns;
+mod metrics;
+mod mqtt;
+mod mtls;
+mod otel;
+mod post;
+mod power;
+mod processes;
+mod push;
+mod screen;
+mod security;
+mod setup;
+mod snapshot;
+mod systemd;
+mod theme;
+mod tui;
+mod units;
+
 use std::env;
 use std::net::{IpAddr, Ipv4Addr};
 use std::path::PathBuf;
 use std::process::ExitCode;
 use std::{convert::Infallible, net::SocketAddr};
-use std::{sync::Arc, time::Duration};
+use std::{sync::Arc, time::Duration, time::SystemTime};
 
 use axum::extract::{ConnectInfo, Host};
-use axum::handler::HandlerWithoutStateExt;
 use axum::response::sse::KeepAlive;
 use axum::response::Redirect;
 use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
+use rustls::server::danger::ClientCertVerifier;
+use rustls::ServerConfig;
 use tokio::signal;
-use tokio::sync::{watch, Mutex};
+use tokio::sync::{mpsc, watch, Mutex};
 use tokio::time::sleep;
-use tokio_stream::wrappers::WatchStream;
+use tokio_stream::wrappers::{ReceiverStream, WatchStream};
 use tokio_stream::{Stream, StreamExt as _};
 
 use askama::Template;
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Query, State},
+    http::{header, uri::Authority, HeaderMap, StatusCode, Uri},
+    middleware,
     response::{sse::Event, Html, IntoResponse, Response, Sse},
     routing::get,
-    Router,
+    Extension, Router,
 };
+use tower_http::compression::{predicate::DefaultPredicate, CompressionLayer};
+use tower_http::sensitive_headers::SetSensitiveRequestHeadersLayer;
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
-use tracing::Level;
-use tracing_subscriber::{filter, Layer};
+use tracing::{Instrument as _, Level};
+use tracing_subscriber::{filter, reload, Layer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use sysinfo::{Disks, Networks, ProcessesToUpdate, System};
+use sysinfo::{Disks, Networks, System};
+
+use access_log::{AccessLog, AccessLogArgs};
+use admin::{AdminArgs, Sessions};
+use agent::{Agent, AgentArgs};
+use boot::BootMode;
+use config::ConfigFile;
+use console::ConsoleArgs;
+use containers::{ContainerStatus, ContainersArgs};
+use hub::{Hub, HubArgs};
+use listeners::{ListenerConfig, Purpose, TlsConnection};
+use log_file::{LogRotationArgs, RotatingFile};
+use log_filter::{LogFilter, LogFilterCommand};
+use mdns::{Mdns, MdnsArgs};
+use mqtt::MqttArgs;
+use mtls::ClientCertRoutes;
+use otel::{OtelArgs, Telemetry};
+use post::{PostArgs, PostResults};
+use power::{Power, PowerArgs};
+use processes::{ProcessesArgs, TopProcesses, UserNames};
+use push::PushArgs;
+use security::{CspNonce, SecurityArgs, SecurityHeaders};
+use snapshot::SystemSnapshot;
+use theme::{SelectedTheme, Theme};
+use tui::TuiCommand;
+use units::{UnitStatus, UnitsArgs};
 
 #[derive(Parser, Debug)]
 #[command(version = env!("RSPI_BIOS_VERSION"), about)]
+#[allow(clippy::struct_excessive_bools)]
 struct Args {
     #[arg(long, value_parser = parse_duration, default_value = "5")]
     system_refresh_interval: Duration,
@@ -51,6 +114,17 @@ struct Args {
     #[arg(long, value_parser = parse_duration, default_value = "1")]
     sse_keep_alive_interval: Duration,
 
+    /// `/readyz` fails if no system information was collected within this many `system_refresh_interval`s
+    #[arg(long, default_value_t = 3)]
+    readiness_max_missed_intervals: u32,
+
+    /// Listener definition, can be repeated. Overrides `--ip-address`, `--http-port`, `--https-port` and
+    /// `--https-redirect`. Format: `[dashboard|metrics|redirect=](http|https)://(<ip>:<port>|unix:<path>|fd:<name>)`
+    /// e.g. `https://[::]:443`, `redirect=http://0.0.0.0:80`, `metrics=http://127.0.0.1:9100`,
+    /// `http://unix:/run/rspi-bios/dashboard.sock`, `https://fd:https` (systemd socket)
+    #[arg(long)]
+    listen: Vec<ListenerConfig>,
+
     #[arg(long)]
     https_redirect: bool,
 
@@ -72,9 +146,29 @@ struct Args {
     #[arg(long, default_value = "key.pem")]
     tls_key_file_name: String,
 
+    /// Only offer HTTP/1.1 over TLS (ALPN), HTTP/2 is offered by default
+    #[arg(long)]
+    disable_http2: bool,
+
+    /// PEM bundle of CAs issuing client certificates, enables mutual TLS
+    ///
+    /// Client certificates are optional unless required for a route using `--require-client-cert`
+    #[arg(long)]
+    tls_client_ca_file: Option<PathBuf>,
+
+    /// Routes that need a client certificate issued by `--tls-client-ca-file`, can be repeated. `/healthz` and
+    /// `/readyz` never do
+    #[arg(long, value_enum)]
+    require_client_cert: Vec<ClientCertRoutes>,
+
     #[arg(long, default_value_os_t = PathBuf::from("/var/log/rspi-bios/"))]
     log_path: PathBuf,
 
+    /// TOML file storing settings changed in SETUP, its values override the matching parameters.
+    /// Changes only last until a restart if not set
+    #[arg(long)]
+    config_file: Option<PathBuf>,
+
     #[arg(long, default_value = "0, 0, 0, 0")]
     system_stream_error_data: String,
 
@@ -102,6 +196,14 @@ struct Args {
     #[arg(long, default_value = "aarch64")]
     cpu_arch_fallback: String,
 
+    /// Look of the dashboard and SETUP, can be changed with `?theme=` (remembered in a cookie) or in SETUP
+    #[arg(long, value_enum, default_value_t = Theme::Award)]
+    theme: Theme,
+
+    /// Boot animation before the health screen, `full` is shortened to `fast` for returning visitors, `?skip=1` skips it
+    #[arg(long, value_enum, default_value_t = BootMode::Full)]
+    boot_mode: BootMode,
+
     /// Send DEBUG events to STDOUT in release
     #[arg(long)]
     force_debug_stdout: bool,
@@ -109,6 +211,66 @@ struct Args {
     /// Place debug log file in the same directory as the binary (overrides `--log_path`)
     #[arg(long)]
     force_debug_local: bool,
+
+    #[command(flatten)]
+    admin: AdminArgs,
+
+    #[command(flatten)]
+    power: PowerArgs,
+
+    #[command(flatten)]
+    units: UnitsArgs,
+
+    #[command(flatten)]
+    containers: ContainersArgs,
+
+    #[command(flatten)]
+    processes: ProcessesArgs,
+
+    #[command(flatten)]
+    log_rotation: LogRotationArgs,
+
+    #[command(flatten)]
+    access_log: AccessLogArgs,
+
+    #[command(flatten)]
+    mqtt: MqttArgs,
+
+    #[command(flatten)]
+    push: PushArgs,
+
+    #[command(flatten)]
+    security: SecurityArgs,
+
+    #[command(flatten)]
+    otel: OtelArgs,
+
+    #[command(flatten)]
+    post: PostArgs,
+
+    #[command(flatten)]
+    console: ConsoleArgs,
+
+    #[command(flatten)]
+    hub: HubArgs,
+
+    #[command(flatten)]
+    agent: AgentArgs,
+
+    #[command(flatten)]
+    mdns: MdnsArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Subcommands, the server is started if none is given.
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// View or change the log filter of a running instance through the admin API
+    LogFilter(LogFilterCommand),
+    /// Show the dashboard in the terminal, from a running instance or collected locally
+    Tui(TuiCommand),
 }
 
 /// Used for parsing [`Duration`] in [`clap`] CLI parameters e.g. `graceful_shutdown_duration`
@@ -119,6 +281,17 @@ fn parse_duration(arg: &str) -> Result<Duration, std::num::ParseIntError> {
     Ok(Duration::from_secs(seconds))
 }
 
+/// Reads the board model from the device tree e.g. `Raspberry Pi 4 Model B Rev 1.4`.
+///
+/// Returns `fallback` if it isn't available e.g. when not running on a Pi.
+fn detect_model_name(fallback: &str) -> String {
+    std::fs::read_to_string("/proc/device-tree/model")
+        .ok()
+        .map(|m| m.trim_end_matches('\0').trim().to_string())
+        .filter(|m| !m.is_empty())
+        .unwrap_or_else(|| fallback.to_string())
+}
+
 /// State used by different parts of the app, including [`tokio`] tasks.
 ///
 /// Has to be thread safe.
@@ -127,6 +300,30 @@ fn parse_duration(arg: &str) -> Result<Duration, std::num::ParseIntError> {
 struct AppState {
     args: Mutex<Args>,
     system_tx: watch::Sender<Event>,
+    snapshot_tx: watch::Sender<SystemSnapshot>,
+    /// Latest state of `--status-unit`s, empty if there are none
+    units_tx: watch::Sender<Vec<UnitStatus>>,
+    /// Latest state of every container, empty without `--container-socket`
+    containers_tx: watch::Sender<Vec<ContainerStatus>>,
+    /// Latest processes using the most CPU and memory, empty if `--top-processes` is `0`
+    processes_tx: watch::Sender<TopProcesses>,
+    tls_loaded: bool,
+    started_at: SystemTime,
+    log_filter: LogFilter,
+    /// Bearer token for `/admin/*`, the admin API is disabled if [`None`]
+    admin_token: Option<String>,
+    /// Logins through the SETUP screen
+    sessions: Sessions,
+    /// Reboot, power off and unit restarts, disabled if [`None`]
+    power: Option<Power>,
+    /// Latest power-on self-test results, shared by every `/sse?post=1` client
+    post_results: PostResults,
+    config_file: Option<PathBuf>,
+    /// Settings saved in `config_file`, already applied to `args`
+    config: Mutex<ConfigFile>,
+    /// Settings from the CLI parameters, restored by "Load Defaults" in SETUP
+    setup_defaults: ConfigFile,
+    model_name: String,
     system: Mutex<System>,
     kernel_version: Mutex<String>,
     os_version: Mutex<String>,
@@ -144,13 +341,28 @@ struct AppState {
 ///
 /// Spawns [`tokio`] tasks to handle:
 /// - Graceful shutdown
-/// - `HTTP` to `HTTPS` redirection
 /// - Expensive system information fetching
-/// - `HTTPS` server
+/// - Publishing system information to MQTT (optional)
+/// - Pushing system information to `InfluxDB` and `StatsD`/Graphite (optional)
+/// - Polling the state of systemd units (optional)
+/// - Polling the state of Docker/Podman containers (optional)
+/// - Polling other instances in hub mode (optional)
+/// - Pushing system information to a hub in agent mode (optional)
+/// - Advertising the dashboard over mDNS and finding peers for the hub
+/// - Servers for every listener e.g. `HTTPS` dashboard, `HTTP` to `HTTPS` redirection
 #[allow(clippy::too_many_lines)]
 #[tokio::main]
 async fn main() -> ExitCode {
-    let args = Args::parse();
+    let mut args = Args::parse();
+
+    // `reqwest` clients (push outputs, subcommands) don't pick a `rustls` provider themselves
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+    match args.command.take() {
+        Some(Command::LogFilter(command)) => return log_filter::run_command(command).await,
+        Some(Command::Tui(command)) => return tui::run_command(command).await,
+        None => {}
+    }
 
     let exe_path = match env::current_exe() {
         Ok(e) => e,
@@ -161,33 +373,46 @@ async fn main() -> ExitCode {
     };
     let log_path = get_log_path(&exe_path, &args.log_path, args.force_debug_local);
 
-    let log_file_result = std::fs::OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(&log_path);
+    let log_file_result = RotatingFile::open(log_path.clone(), args.log_rotation.clone());
 
     let log_file = match log_file_result {
         Ok(l) => l,
         Err(e) => {
-            eprintln!("Failed to open/create {log_path:?}, did you set the correct permissions?\n\nError={e:#?}");
+            eprintln!(
+                "Failed to open/create {}, did you set the correct permissions?\n\nError={e:#?}",
+                log_path.display()
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Reloadable so the filter can be changed at runtime through the admin API
+    let (env_filter, log_filter_handle) = reload::Layer::new(
+        tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+            format!(
+                "{}=debug,tower_http=debug,axum::rejection=trace",
+                env!("CARGO_CRATE_NAME")
+            )
+            .into()
+        }),
+    );
+
+    let model_name = detect_model_name(&args.model_name_fallback);
+    let mut telemetry = match Telemetry::new(&args.otel, &model_name) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Failed to set up OpenTelemetry export.\n\nError={e}");
             return ExitCode::FAILURE;
         }
     };
 
     let subscriber = tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                format!(
-                    "{}=debug,tower_http=debug,axum::rejection=trace",
-                    env!("CARGO_CRATE_NAME")
-                )
-                .into()
-            }),
-        )
+        .with(env_filter)
+        .with(telemetry.as_ref().map(Telemetry::layer))
         .with(
             tracing_subscriber::fmt::layer()
                 .json()
-                .with_writer(log_file),
+                .with_writer(std::sync::Mutex::new(log_file)),
         );
 
     if cfg!(debug_assertions) || args.force_debug_stdout {
@@ -201,56 +426,208 @@ async fn main() -> ExitCode {
             .init();
     }
     tracing::info!("Logging to {log_path:?}");
+
+    let access_log = if args.access_log.disable_access_log {
+        None
+    } else {
+        let access_log_path = log_path.with_file_name("access.log");
+        match AccessLog::open(
+            &args.access_log,
+            access_log_path.clone(),
+            args.log_rotation.clone(),
+        ) {
+            Ok(a) => {
+                tracing::info!("Writing access log to {access_log_path:?}");
+                Some(a)
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to open/create {access_log_path:?}, did you set the correct permissions?");
+                return ExitCode::FAILURE;
+            }
+        }
+    };
     tracing::debug!(
         "Running {} version {}",
         env!("CARGO_CRATE_NAME"),
         env!("RSPI_BIOS_VERSION")
     );
 
-    tracing::info!("Creating TLS config");
-    let cert_dirs_to_search = get_cert_dirs_to_search(&exe_path, &args.tls_dir);
-    let Some(tls_config) = create_tls_config(
-        cert_dirs_to_search,
-        &args.tls_cert_file_name,
-        &args.tls_key_file_name,
-    )
-    .await
-    else {
-        tracing::error!("Failed to create TLS config, did you set the correct permissions? Did you put the .pem files in the correct place?");
+    let setup_defaults = ConfigFile::from_args(&args);
+    let config = match &args.config_file {
+        Some(path) => match ConfigFile::load(path).await {
+            Ok(config) => {
+                tracing::info!("Using settings from {path:?}");
+                config.apply(&mut args);
+                config
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to read config file {path:?}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => ConfigFile::default(),
+    };
+
+    if let Some(watchdog_timeout) = systemd::watchdog_timeout() {
+        if watchdog_timeout <= args.system_refresh_interval {
+            tracing::warn!("systemd watchdog timeout ({watchdog_timeout:?}) is shorter than the system refresh interval, the service will be restarted");
+        }
+    }
+
+    // Use sockets passed in by systemd instead of binding if there are any
+    let activated_sockets = systemd::ActivatedSockets::from_env();
+    let listener_configs = if args.listen.is_empty() {
+        default_listener_configs(&args, &activated_sockets)
+    } else {
+        args.listen.clone()
+    };
+
+    let admin_token = match &args.admin.admin_token_file {
+        Some(path) => {
+            let Some(token) = admin::read_token(path).await else {
+                return ExitCode::FAILURE;
+            };
+            tracing::info!("Admin API enabled");
+            Some(token)
+        }
+        None => None,
+    };
+
+    let power = match Power::new(
+        &args.power,
+        admin_token.is_some(),
+        &log_path.with_file_name("audit.log"),
+        args.log_rotation.clone(),
+    ) {
+        Ok(p) => p,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to set up power actions");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if !args.require_client_cert.is_empty() && args.tls_client_ca_file.is_none() {
+        tracing::error!(
+            "--require-client-cert needs --tls-client-ca-file to verify client certificates"
+        );
         return ExitCode::FAILURE;
+    }
+
+    let tls_required = listener_configs.iter().any(|l| l.tls);
+    let tls_config = if tls_required {
+        tracing::info!("Creating TLS config");
+        let client_cert_verifier = match &args.tls_client_ca_file {
+            Some(ca_file) => {
+                let Some(verifier) = mtls::client_cert_verifier(ca_file).await else {
+                    return ExitCode::FAILURE;
+                };
+                Some(verifier)
+            }
+            None => None,
+        };
+        let cert_dirs_to_search = get_cert_dirs_to_search(&exe_path, &args.tls_dir);
+        let Some(tls_config) = create_tls_config(
+            cert_dirs_to_search,
+            &args.tls_cert_file_name,
+            &args.tls_key_file_name,
+            client_cert_verifier,
+            !args.disable_http2,
+        )
+        .await
+        else {
+            tracing::error!("Failed to create TLS config, did you set the correct permissions? Did you put the .pem files in the correct place?");
+            return ExitCode::FAILURE;
+        };
+        Some(tls_config)
+    } else {
+        None
     };
 
-    // Create a handle for our TLS server so the shutdown signal can all shutdown
+    // Create a handle for our servers so the shutdown signal can all shutdown
     let handle = axum_server::Handle::new();
+    // Servers not managed by `handle` e.g. unix sockets
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
     // Spawn a task to gracefully shutdown server.
     tracing::debug!("Spawning graceful shutdown handler");
     let graceful_shutdown_task = tokio::spawn(graceful_shutdown(
         handle.clone(),
+        shutdown_tx,
         args.graceful_shutdown_duration,
         args.alive_connections_check_interval,
     ));
 
-    let https_redirect_task = if args.https_redirect {
-        // Spawn a second server to redirect http requests to this server
-        tokio::spawn(redirect_http_to_https(
-            args.ip_address,
-            args.http_port,
-            args.https_port,
-        ))
-    } else {
-        // TODO: Find out if there is a better way than doing this
-        tokio::spawn(async {
-            let mut interval = tokio::time::interval(Duration::from_secs(1));
-            loop {
-                interval.tick().await;
+    let tx = watch::Sender::new(Event::default().data(&args.system_stream_error_data));
+    let snapshot_tx = watch::Sender::new(SystemSnapshot::default());
+
+    if let Some(mqtt_host) = args.mqtt.mqtt_host.clone() {
+        tracing::debug!("Spawning MQTT publisher");
+        tokio::spawn(mqtt::publish_system_messages(
+            args.mqtt.clone(),
+            mqtt_host,
+            model_name.clone(),
+            snapshot_tx.subscribe(),
+        ));
+    }
+
+    for output in push::create_push_outputs(&args.push).await {
+        tracing::debug!("Spawning push output");
+        tokio::spawn(push::push_system_messages(output, snapshot_tx.subscribe()));
+    }
+
+    if let Some(telemetry) = &mut telemetry {
+        telemetry.observe_snapshots(&snapshot_tx.subscribe());
+    }
+
+    let hub = if args.hub.enabled() {
+        match Hub::new(&args.hub).await {
+            Ok(h) => {
+                tracing::info!("Hub mode enabled");
+                Some(h)
             }
-        })
+            Err(e) => {
+                tracing::error!("Failed to set up hub mode. Error: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        None
     };
 
-    let addr = SocketAddr::from((args.ip_address, args.https_port));
+    let agent = match Agent::new(&args.agent).await {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::error!("Failed to set up agent mode. Error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
 
-    let tx = watch::Sender::new(Event::default().data(&args.system_stream_error_data));
+    // Ports used to rewrite the `Host` header when redirecting to HTTPS
+    let redirect_from_port = listener_configs
+        .iter()
+        .filter(|l| l.purpose == Purpose::Redirect)
+        .find_map(ListenerConfig::tcp_port)
+        .unwrap_or(args.http_port);
+    let redirect_to_port = listener_configs
+        .iter()
+        .filter(|l| l.purpose == Purpose::Dashboard && l.tls)
+        .find_map(ListenerConfig::tcp_port)
+        .unwrap_or(args.https_port);
+    let mdns = Mdns::start(
+        &args.mdns,
+        listener_configs
+            .iter()
+            .filter(|l| l.purpose == Purpose::Dashboard && l.tls)
+            .find_map(ListenerConfig::tcp_port),
+        &model_name,
+        hub.clone(),
+    );
+    let graceful_shutdown_duration = args.graceful_shutdown_duration;
+    let security_headers = SecurityHeaders::new(&args.security);
+    let require_client_cert = args.require_client_cert.clone();
+    let console_args = args.console.clone();
+    let units_args = args.units.clone();
+    let containers_args = args.containers.clone();
 
     // Create our shared state
     tracing::debug!("Creating initial state");
@@ -262,8 +639,23 @@ async fn main() -> ExitCode {
             System::long_os_version().unwrap_or_else(|| args.os_version_fallback.clone()),
         ),
         cpu_arch: Mutex::new(System::cpu_arch().unwrap_or_else(|| args.cpu_arch_fallback.clone())),
+        tls_loaded: !tls_required || tls_config.is_some(),
+        started_at: SystemTime::now(),
+        log_filter: LogFilter::new(log_filter_handle, args.admin.log_filter_revert_after),
+        admin_token,
+        sessions: Sessions::new(args.admin.admin_session_duration),
+        power,
+        post_results: PostResults::default(),
+        config_file: args.config_file.clone(),
+        config: Mutex::new(config),
+        setup_defaults,
+        model_name,
         args: Mutex::new(args),
         system_tx: tx,
+        snapshot_tx,
+        units_tx: watch::Sender::new(Vec::new()),
+        containers_tx: watch::Sender::new(Vec::new()),
+        processes_tx: watch::Sender::new(TopProcesses::default()),
         system: Mutex::new(System::new_all()),
         disks: Mutex::new(Disks::new_with_refreshed_list()),
         networks: Mutex::new(Networks::new_with_refreshed_list()),
@@ -273,26 +665,60 @@ async fn main() -> ExitCode {
     tracing::debug!("Spawning system info stream");
     let system_messages_task = tokio::spawn(send_system_messages(state.clone()));
 
-    // Spawn main server
-    let https_server_task = tokio::spawn(https_server(addr, state, tls_config, handle));
+    if console_args.enabled() {
+        tracing::debug!("Spawning console output");
+        tokio::spawn(console::draw_system_messages(console_args, state.clone()));
+    }
+
+    tracing::debug!("Spawning systemd unit watcher");
+    tokio::spawn(units::watch_units(units_args, state.clone()));
+
+    tracing::debug!("Spawning container watcher");
+    tokio::spawn(containers::watch_containers(containers_args, state.clone()));
 
-    // Wait for a task to complete, useful if 'https_redirect_task' returns error for example.
+    if let Some(agent) = agent {
+        tracing::debug!("Spawning hub agent");
+        tokio::spawn(agent::push_system_messages(agent, state.clone()));
+    }
+
+    // Outermost layer so the logged status and bytes are what was actually sent
+    let with_access_log = |router: Router| match &access_log {
+        Some(access_log) => router.layer(middleware::from_fn_with_state(
+            access_log.clone(),
+            access_log::access_log_middleware,
+        )),
+        None => router,
+    };
+    let routers = listeners::Routers {
+        dashboard: with_access_log(dashboard_router(
+            state.clone(),
+            hub,
+            security_headers,
+            require_client_cert.contains(&ClientCertRoutes::Dashboard),
+        )),
+        metrics: with_access_log(metrics_router(
+            state.clone(),
+            require_client_cert.contains(&ClientCertRoutes::Metrics),
+        )),
+        redirect: with_access_log(redirect_router(redirect_from_port, redirect_to_port)),
+    };
+
+    // Spawn servers
+    let servers_task = tokio::spawn(listeners::serve_listeners(
+        listener_configs,
+        activated_sockets,
+        routers,
+        tls_config,
+        handle,
+        shutdown_rx,
+        graceful_shutdown_duration,
+    ));
+
+    // Wait for a task to complete, useful if 'servers_task' returns error for example.
     tokio::select! {
         _ = graceful_shutdown_task => {},
-        result = https_redirect_task => {
-            match result {
-                Ok(s) => {
-                    if !s {
-                        return ExitCode::FAILURE;
-                    }
-                },
-                Err(_) => {
-                    return ExitCode::FAILURE
-                },
-            }
-        },
         _ = system_messages_task => {},
-        result = https_server_task => {
+        result = servers_task => {
             match result {
                 Ok(s) => {
                     if !s {
@@ -306,55 +732,131 @@ async fn main() -> ExitCode {
         },
     }
 
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown();
+    }
+    if let Some(mdns) = mdns {
+        mdns.shutdown();
+    }
+
     tracing::info!("Goodbye");
     ExitCode::SUCCESS
 }
 
-/// Runs the main `HTTPS` server.
-///
-/// Returns `false` if starting the server fails e.g. if unable to bind to port.
-async fn https_server(
-    addr: std::net::SocketAddr,
+/// Creates the [`Router`] for the main dashboard.
+fn dashboard_router(
     state: Arc<AppState>,
-    tls_config: RustlsConfig,
-    handle: axum_server::Handle,
-) -> bool {
-    let app = Router::new()
+    hub: Option<Arc<Hub>>,
+    security_headers: Option<Arc<SecurityHeaders>>,
+    require_client_cert: bool,
+) -> Router {
+    let router = Router::new()
         .fallback(get(|| async { Redirect::permanent("/") }))
         .route("/", get(index_handler))
         .route("/sse", get(sse_handler))
+        .route("/api/snapshot", get(api::snapshot_handler))
+        .route("/api/units", get(units::units_handler))
+        .route("/api/containers", get(containers::api_containers_handler))
+        .route("/containers", get(containers::containers_handler))
+        .route("/api/processes", get(processes::api_processes_handler))
+        .route("/processes", get(processes::processes_handler))
+        .route("/snapshot.png", get(screen::snapshot_png_handler))
+        .route("/static/*path", get(assets::static_handler))
+        .merge(setup::setup_routes(state.clone()));
+
+    let router = if state.admin_token.is_some() {
+        router.merge(admin::admin_routes(state.clone()))
+    } else {
+        router
+    };
+    let router = if state.power.is_some() {
+        router.merge(power::power_routes(state.clone()))
+    } else {
+        router
+    };
+    let router = match hub {
+        Some(hub) => router.merge(hub::hub_routes(hub)),
+        None => router,
+    };
+    // After merging the routes it covers, `route_layer` only applies to routes that already exist
+    let router = mtls::enforce(router, require_client_cert);
+
+    let router = router
         // logging so we can see whats going on
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(DefaultMakeSpan::default().include_headers(true)),
         )
+        // hide credentials from the traced headers
+        .layer(SetSensitiveRequestHeadersLayer::new([
+            axum::http::header::AUTHORIZATION,
+            axum::http::header::COOKIE,
+        ]))
+        // added after the trace layer so frequent health checks don't flood the logs
+        .route("/healthz", get(health::healthz_handler))
+        .route("/readyz", get(health::readyz_handler))
+        // gzip/brotli, the default predicate skips SSE (`text/event-stream`) so events aren't buffered
+        .layer(CompressionLayer::new().compress_when(DefaultPredicate::new()))
         .with_state(state);
 
-    tracing::info!("Starting HTTPS server at {addr}");
-    let axum_result = axum_server::bind_rustls(addr, tls_config)
-        .handle(handle)
-        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
-        .await;
+    match security_headers {
+        Some(security_headers) => router.layer(middleware::from_fn_with_state(
+            security_headers,
+            security::security_headers_middleware,
+        )),
+        None => router,
+    }
+}
 
-    if let Err(e) = axum_result {
-        tracing::error!(error = %e, "Failed to start HTTPS server at {addr}, did you set the correct permissions?");
-        return false;
-    };
+/// Creates the [`Router`] for machine-readable endpoints, not traced since they are scraped often.
+fn metrics_router(state: Arc<AppState>, require_client_cert: bool) -> Router {
+    let router = Router::new().route("/metrics", get(metrics::metrics_handler));
 
-    true
+    mtls::enforce(router, require_client_cert)
+        .route("/healthz", get(health::healthz_handler))
+        .route("/readyz", get(health::readyz_handler))
+        .layer(CompressionLayer::new().compress_when(DefaultPredicate::new()))
+        .with_state(state)
 }
 
-/// Runs the server that redirects `HTTP` requests to the `HTTPS` server.
+/// Creates the listener definitions used when `--listen` isn't provided.
 ///
-/// Returns `false` if starting the server fails e.g. if unable to bind to port.
+/// Uses sockets passed in by systemd if there are any, otherwise `--ip-address`, `--https-port` and
+/// `--http-port` (only with `--https-redirect`).
+fn default_listener_configs(
+    args: &Args,
+    activated_sockets: &systemd::ActivatedSockets,
+) -> Vec<ListenerConfig> {
+    let address = |name: &str, port: u16| {
+        if activated_sockets.contains(name) {
+            listeners::Address::Systemd(name.to_string())
+        } else {
+            listeners::Address::Tcp(SocketAddr::from((args.ip_address, port)))
+        }
+    };
+
+    let mut configs = vec![ListenerConfig {
+        purpose: Purpose::Dashboard,
+        tls: true,
+        address: address("https", args.https_port),
+    }];
+
+    if args.https_redirect {
+        configs.push(ListenerConfig {
+            purpose: Purpose::Redirect,
+            tls: false,
+            address: address("http", args.http_port),
+        });
+    }
+
+    configs
+}
+
+/// Creates the [`Router`] that redirects `HTTP` requests to the `HTTPS` server.
 ///
 /// Based on example code from: <https://github.com/tokio-rs/axum/blob/6efcb75d99a437fa80c81e2308ec8234b023e1a7/examples/tls-rustls/src/main.rs>
 #[allow(clippy::similar_names)]
-async fn redirect_http_to_https(
-    ip_address: std::net::IpAddr,
-    http_port: u16,
-    https_port: u16,
-) -> bool {
+fn redirect_router(http_port: u16, https_port: u16) -> Router {
     fn make_https(
         host: &str,
         uri: axum::http::Uri,
@@ -392,35 +894,11 @@ async fn redirect_http_to_https(
         }
     };
 
-    let addr = SocketAddr::from((ip_address, http_port));
-
-    tracing::info!("Starting HTTP redirect server at {addr}");
-    let listener = match tokio::net::TcpListener::bind(addr).await {
-        Ok(l) => l,
-        Err(e) => {
-            tracing::error!(error = %e, "Failed to start HTTPS redirect server at {addr}, did you set the correct permissions?");
-            return false;
-        }
-    };
-
-    let axum_result = axum::serve(
-        listener,
-        redirect.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await;
-
-    if let Err(e) = axum_result {
-        tracing::error!(error = %e, "Failed to start HTTPS redirect server at {addr}, did you set the correct permissions?");
-        return false;
-    };
-
-    true
+    Router::new().fallback(redirect)
 }
 
-/// Describes data used in the provided [`askama`] `index.html` template.
-#[derive(Template)]
-#[template(path = "index.html")]
-struct IndexTemplate {
+/// Describes data used in the `<theme>/index.html` [`askama`] templates.
+struct IndexData {
     kernel_version: String,
     model_name: String,
     cpu_brand: String,
@@ -438,6 +916,11 @@ struct IndexTemplate {
     version: String,
     os_version: String,
     cpu_arch: String,
+    csp_nonce: String,
+    /// [`BootMode::as_str`]
+    boot_mode: &'static str,
+    /// Absolute `/snapshot.png` URL, empty if the host is unknown
+    og_image: String,
 }
 
 /// Generic [`askama`] template type.
@@ -460,29 +943,49 @@ where
     }
 }
 
+/// Query parameters of `GET /`.
+#[derive(serde::Deserialize)]
+struct IndexQuery {
+    /// Overrides the theme for this and following pages, see [`SelectedTheme::select`]
+    theme: Option<String>,
+    /// Skips (`1`) or replays (`0`) the boot animation, see [`BootMode::select`]. A string so other values are
+    /// ignored instead of rejecting the request
+    skip: Option<String>,
+}
+
 /// Handles `GET` requests to `index.html`.
 ///
 /// Takes [`sysinfo`] data, formats it into a nicer looking format, and returns a rendered [`askama`] template containing it.
 async fn index_handler(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    tls: Option<Extension<TlsConnection>>,
+    csp_nonce: Option<Extension<CspNonce>>,
+    Query(query): Query<IndexQuery>,
+    uri: Uri,
+    headers: HeaderMap,
     state: State<Arc<AppState>>,
-) -> impl IntoResponse {
-    tracing::info!("Connection made to index.html from {addr}");
-    // TODO: Get model name from `tail -n 1 /proc/cpuinfo | cut -d':' -f2 | cut -c2-`
-
+) -> Response {
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    let client_subject = tls.and_then(|Extension(t)| t.client_subject);
+    tracing::info!(
+        client_subject = client_subject.as_deref(),
+        "Connection made to index.html from {addr}"
+    );
     let (
+        theme,
+        boot_mode,
         cpu_brand_fallback,
         cpu_frequency_fallback,
         disk_space_fallback,
-        model_name_fallback,
         cpu_brand_short_fallback,
     ) = {
         let args = state.args.lock().await;
         (
+            SelectedTheme::select(query.theme.as_deref(), &headers, args.theme),
+            BootMode::select(query.skip.as_deref(), &headers, args.boot_mode),
             args.cpu_brand_fallback.clone(),
             args.cpu_frequency_fallback,
             args.disk_space_fallback,
-            args.model_name_fallback.clone(),
             args.cpu_brand_short_fallback.clone(),
         )
     };
@@ -493,7 +996,7 @@ async fn index_handler(
             system
                 .cpus()
                 .first()
-                .map_or_else(|| cpu_brand_fallback.to_string(), |c| c.brand().to_string()),
+                .map_or_else(|| cpu_brand_fallback.clone(), |c| c.brand().to_string()),
             system.cpus().len(),
             system
                 .cpus()
@@ -524,20 +1027,20 @@ async fn index_handler(
         }
     }
 
-    let template = IndexTemplate {
+    let data = IndexData {
         kernel_version: state.kernel_version.lock().await.to_string(), // 6.6.31+rpt-rpi-v8
-        model_name: model_name_fallback.to_string(),
-        cpu_brand: cpu_brand.to_string(), // Cortex-A72
+        model_name: state.model_name.clone(),
+        cpu_brand: cpu_brand.clone(), // Cortex-A72
         cpu_brand_short: cpu_brand
             .get(0..cpu_brand.len() - 2)
             .unwrap_or(&cpu_brand_short_fallback)
             .to_string()
             .to_uppercase(), // CORTEX-A
-        cpu_count,                        // 4
-        cpu_speed,                        // 1800 MHz
+        cpu_count,                    // 4
+        cpu_speed,                    // 1800 MHz
         extended_memory: (total_memory - (1_048_576_000)) / 1_000, // 4 GB
-        primary_disk_size,                // 32 GB
-        total_memory,                     // 4 GB
+        primary_disk_size,            // 32 GB
+        total_memory,                 // 4 GB
         rounded_memory: (total_memory / 1_000_000_000 + 3) & !3, // 4 GB
         uptime: System::uptime().to_string(),
         process_count,
@@ -546,9 +1049,33 @@ async fn index_handler(
         version: env!("RSPI_BIOS_VERSION").to_string(),
         os_version: state.os_version.lock().await.to_string(),
         cpu_arch: state.cpu_arch.lock().await.to_string(),
+        // empty if security headers are disabled
+        csp_nonce: csp_nonce.map(|Extension(n)| n.0).unwrap_or_default(),
+        boot_mode: boot_mode.as_str(),
+        // Link previews need an absolute URL, also keeps the theme of the link
+        og_image: headers
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            // HTTP/2 sends the host as part of the URI
+            .or_else(|| uri.authority().map(Authority::as_str))
+            .map_or_else(String::new, |host| {
+                format!(
+                    "{scheme}://{host}/snapshot.png?theme={}",
+                    theme.theme.as_str()
+                )
+            }),
     };
 
-    HtmlTemplate(template)
+    let mut response = theme.render_index(&data);
+    BootMode::remember(&headers, &mut response);
+    response
+}
+
+/// Query parameters of `GET /sse`.
+#[derive(serde::Deserialize)]
+struct SseQuery {
+    /// Runs the power-on self-test (`1`) and sends its results as `post` events
+    post: Option<u8>,
 }
 
 /// Handles [Server-Sent Events (SSE)](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events/Using_server-sent_events).
@@ -556,51 +1083,118 @@ async fn index_handler(
 /// Uses a [`WatchStream`] to only send users the latest information.
 async fn sse_handler(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    tls: Option<Extension<TlsConnection>>,
+    Query(query): Query<SseQuery>,
     state: State<Arc<AppState>>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    tracing::info!("Connection made to SSE from {addr}");
+    let client_subject = tls.and_then(|Extension(t)| t.client_subject);
+    tracing::info!(
+        client_subject = client_subject.as_deref(),
+        "Connection made to SSE from {addr}"
+    );
 
     let system_rx = state.system_tx.subscribe();
 
     let system_stream = WatchStream::from_changes(system_rx).map(Ok);
 
-    Sse::new(system_stream)
-        .keep_alive(KeepAlive::new().interval(state.args.lock().await.sse_keep_alive_interval))
+    // The sender is dropped right away without `?post=1`, ending the stream
+    let (post_tx, post_rx) = mpsc::channel(8);
+    if query.post == Some(1) {
+        let post_args = state.args.lock().await.post.clone();
+        tokio::spawn(post::run(post_args, state.0.clone(), post_tx));
+    }
+    let post_stream = ReceiverStream::new(post_rx).map(Ok);
+
+    // Starts with the current units so the list can be drawn right away, empty lists are sent too so units that
+    // stopped failing disappear
+    let units_enabled = state.args.lock().await.units.enabled();
+    let units_stream = WatchStream::new(state.units_tx.subscribe())
+        .filter(move |_| units_enabled)
+        .filter_map(|units| Event::default().event("units").json_data(units).ok())
+        .map(Ok);
+
+    let containers_enabled = state.args.lock().await.containers.enabled();
+    let containers_stream = WatchStream::new(state.containers_tx.subscribe())
+        .filter(move |_| containers_enabled)
+        .filter_map(|containers| {
+            Event::default()
+                .event("containers")
+                .json_data(containers)
+                .ok()
+        })
+        .map(Ok);
+
+    let processes_enabled = state.args.lock().await.processes.enabled();
+    let processes_stream = WatchStream::from_changes(state.processes_tx.subscribe())
+        .filter(move |_| processes_enabled)
+        .filter_map(|processes| {
+            Event::default()
+                .event("processes")
+                .json_data(processes)
+                .ok()
+        })
+        .map(Ok);
+
+    Sse::new(
+        system_stream
+            .merge(post_stream)
+            .merge(units_stream)
+            .merge(containers_stream)
+            .merge(processes_stream),
+    )
+    .keep_alive(KeepAlive::new().interval(state.args.lock().await.sse_keep_alive_interval))
 }
 
 /// Creates SSE events used by `sse_handler()`.
 ///
 /// Some system information is expensive to fetch for every connection, this function does so once every couple of
 /// seconds and sends it to `sse_handler()` instances over a [`watch`].
+///
+/// The same information is also sent as a [`SystemSnapshot`] for other outputs e.g. MQTT.
 async fn send_system_messages(state: Arc<AppState>) {
-    let mut interval = tokio::time::interval(state.args.lock().await.system_refresh_interval);
+    let (mut interval, processes_args) = {
+        let args = state.args.lock().await;
+        (
+            tokio::time::interval(args.system_refresh_interval),
+            args.processes.clone(),
+        )
+    };
+    let mut users = UserNames::new();
     loop {
         interval.tick().await;
 
-        let uptime = System::uptime();
-
-        let process_count = {
-            let mut system = state.system.lock().await;
-            system.refresh_processes(ProcessesToUpdate::All);
-            system.processes().len()
-        };
-
-        let mut total_rx = 0;
-        let mut total_tx = 0;
-        {
-            let mut networks = state.networks.lock().await;
-            networks.refresh();
+        // Can be changed in SETUP
+        let system_refresh_interval = state.args.lock().await.system_refresh_interval;
+        if interval.period() != system_refresh_interval {
+            interval = tokio::time::interval(system_refresh_interval);
+            interval.tick().await;
+        }
 
-            for (_interface_name, data) in networks.iter() {
-                total_rx += data.total_received();
-                total_tx += data.total_transmitted();
-            }
-        };
+        // Exported as a span with `--otlp-endpoint`
+        async {
+            let (snapshot, top_sample) = {
+                let mut system = state.system.lock().await;
+                let mut networks = state.networks.lock().await;
+                let snapshot =
+                    SystemSnapshot::collect(&mut system, &mut networks, processes_args.enabled());
+                drop(networks);
+                let top_sample = TopProcesses::sample(&processes_args, &system);
+                drop(system);
+                (snapshot, top_sample)
+            };
+            let processes = TopProcesses::collect(&top_sample, &mut users);
+
+            let _ = state
+                .system_tx
+                .send(Event::default().data(snapshot.to_sse_data()));
+            state.snapshot_tx.send_replace(snapshot);
+            state.processes_tx.send_replace(processes);
+        }
+        .instrument(tracing::debug_span!("refresh_system_info"))
+        .await;
 
-        let event = Event::default().data(format!(
-            "{total_rx:?}, {total_tx:?}, {process_count:?}, {uptime:?}"
-        ));
-        let _ = state.system_tx.send(event);
+        // A hung collector stops petting the watchdog and gets restarted by systemd
+        systemd::notify_watchdog();
     }
 }
 
@@ -613,6 +1207,7 @@ async fn send_system_messages(state: Arc<AppState>) {
 /// - <https://github.com/programatik29/axum-server/blob/d48b1a931909d156177bc87684910769e67be905/examples/graceful_shutdown.rs>
 async fn graceful_shutdown(
     handle: axum_server::Handle,
+    shutdown_tx: watch::Sender<bool>,
     graceful_shutdown_duration: Duration,
     alive_connections_check_interval: Duration,
 ) {
@@ -644,7 +1239,9 @@ async fn graceful_shutdown(
     // Refuses new connections
     // 10 secs is how long docker will wait to force shutdown
     tracing::info!("Received termination signal, shutting down...");
+    systemd::notify_stopping();
     handle.graceful_shutdown(Some(graceful_shutdown_duration));
+    shutdown_tx.send_replace(true);
 
     // Print alive connection count every second.
     loop {
@@ -655,13 +1252,17 @@ async fn graceful_shutdown(
 
 /// Searches for `.pem` TLS certificate and key files in the provided directories.
 ///
-/// Attempts to create a [`RustlsConfig`] using them.
+/// Attempts to create a [`RustlsConfig`] using them, offering HTTP/2 via ALPN if `http2` is `true`.
+///
+/// Client certificates are requested and verified using `client_cert_verifier` if provided (mutual TLS).
 ///
 /// Returns [`None`] if process was unsuccessful.
 async fn create_tls_config(
     cert_dirs_to_search: Vec<PathBuf>,
     tls_cert_file_name: &str,
     tls_key_file_name: &str,
+    client_cert_verifier: Option<Arc<dyn ClientCertVerifier>>,
+    http2: bool,
 ) -> Option<RustlsConfig> {
     for cert_dir in &cert_dirs_to_search {
         tracing::debug!("Attempting to load TLS .pem files from {cert_dir:?}");
@@ -674,6 +1275,22 @@ async fn create_tls_config(
         match config_result {
             Ok(t) => {
                 tracing::info!("Found TLS {tls_cert_file_name} and {tls_key_file_name} file(s) in {cert_dir:?}");
+
+                let mut server_config = client_cert_verifier.as_ref().map_or_else(
+                    || (*t.get_inner()).clone(),
+                    |verifier| {
+                        ServerConfig::builder()
+                            .with_client_cert_verifier(verifier.clone())
+                            .with_cert_resolver(t.get_inner().cert_resolver.clone())
+                    },
+                );
+                server_config.alpn_protocols = if http2 {
+                    vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+                } else {
+                    vec![b"http/1.1".to_vec()]
+                };
+                t.reload_from_config(Arc::new(server_config));
+
                 return Some(t);
             }
             Err(e) => {
@@ -744,9 +1361,13 @@ fn get_log_path(
     let create_dir_result = std::fs::create_dir_all(parent);
 
     if let Err(e) = create_dir_result {
-        eprintln!("Failed to create parent dirs for {log_path:?}, using {exe_log_path:?} instead. Error: {e:?}");
+        eprintln!(
+            "Failed to create parent dirs for {}, using {} instead. Error: {e:?}",
+            log_path.display(),
+            exe_log_path.display()
+        );
         log_path = exe_log_path;
-    };
+    }
 
     log_path
 }
@@ -0,0 +1,177 @@
+//! RSPI-BIOS mDNS/DNS-SD advertisement and discovery
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use mdns_sd::{Receiver, ServiceDaemon, ServiceEvent, ServiceInfo};
+use sysinfo::System;
+
+use crate::hub::Hub;
+
+/// Service type browsed by hubs.
+const SERVICE_TYPE: &str = "_rspi-bios._tcp.local.";
+/// Generic type so the dashboard also shows up in browsers and tools like `avahi-browse`.
+const HTTPS_SERVICE_TYPE: &str = "_https._tcp.local.";
+
+/// CLI parameters for mDNS, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct MdnsArgs {
+    /// Don't advertise the dashboard over mDNS or browse for other instances in hub mode
+    #[arg(long)]
+    disable_mdns: bool,
+
+    /// mDNS instance name, the host name if not set
+    #[arg(long)]
+    mdns_name: Option<String>,
+}
+
+/// Running mDNS responder, advertising this instance and finding peers for the hub.
+pub(crate) struct Mdns {
+    daemon: ServiceDaemon,
+    /// Full names of the registered services, unregistered on shutdown
+    registered: Vec<String>,
+}
+
+impl Mdns {
+    /// Advertises the dashboard on `port` (the `HTTPS` dashboard listener) and adds every other instance found on
+    /// the network to `hub`.
+    ///
+    /// Returns [`None`] if mDNS is disabled or the responder couldn't be started, errors are logged.
+    pub(crate) fn start(
+        args: &MdnsArgs,
+        port: Option<u16>,
+        model_name: &str,
+        hub: Option<Arc<Hub>>,
+    ) -> Option<Self> {
+        if args.disable_mdns {
+            return None;
+        }
+        let daemon = match ServiceDaemon::new() {
+            Ok(d) => d,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to start mDNS responder");
+                return None;
+            }
+        };
+
+        let host_name = System::host_name().unwrap_or_else(|| env!("CARGO_CRATE_NAME").to_string());
+        let instance_name = args.mdns_name.clone().unwrap_or_else(|| host_name.clone());
+        let mut mdns = Self {
+            daemon,
+            registered: Vec::new(),
+        };
+
+        if let Some(port) = port {
+            let properties = [
+                ("version", env!("RSPI_BIOS_VERSION")),
+                ("model", model_name),
+                ("port", &port.to_string()),
+                ("path", "/"),
+            ];
+            for service_type in [SERVICE_TYPE, HTTPS_SERVICE_TYPE] {
+                mdns.register(
+                    service_type,
+                    &instance_name,
+                    &format!("{host_name}.local."),
+                    port,
+                    &properties,
+                );
+            }
+        } else {
+            // e.g. only unix sockets behind a reverse proxy
+            tracing::info!("No HTTPS dashboard listener on a TCP port, not advertising over mDNS");
+        }
+
+        if let Some(hub) = hub {
+            match mdns.daemon.browse(SERVICE_TYPE) {
+                Ok(receiver) => {
+                    let own_name = format!("{instance_name}.{SERVICE_TYPE}");
+                    tokio::spawn(discover_peers(receiver, hub, own_name));
+                }
+                Err(e) => tracing::error!(error = %e, "Failed to browse for hub peers over mDNS"),
+            }
+        }
+
+        Some(mdns)
+    }
+
+    fn register(
+        &mut self,
+        service_type: &str,
+        instance_name: &str,
+        host_name: &str,
+        port: u16,
+        properties: &[(&str, &str)],
+    ) {
+        let result = ServiceInfo::new(service_type, instance_name, host_name, (), port, properties)
+            .map(ServiceInfo::enable_addr_auto)
+            .and_then(|info| {
+                let fullname = info.get_fullname().to_string();
+                self.daemon.register(info).map(|()| fullname)
+            });
+
+        match result {
+            Ok(fullname) => {
+                tracing::info!("Advertising {fullname} on port {port} over mDNS");
+                self.registered.push(fullname);
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to advertise {service_type} over mDNS");
+            }
+        }
+    }
+
+    /// Unregisters the services so they disappear from browsers right away instead of when their records expire.
+    pub(crate) fn shutdown(self) {
+        for fullname in &self.registered {
+            let _ = self.daemon.unregister(fullname);
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Polls every instance advertising [`SERVICE_TYPE`] like a `--hub-peer`, except this one.
+///
+/// Instances are only added once, later resolutions with other addresses are ignored.
+async fn discover_peers(receiver: Receiver<ServiceEvent>, hub: Arc<Hub>, own_name: String) {
+    let mut found = HashSet::new();
+
+    while let Ok(event) = receiver.recv_async().await {
+        let ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+        let fullname = info.get_fullname();
+        if fullname == own_name || found.contains(fullname) {
+            continue;
+        }
+
+        // IPv4 first, link-local IPv6 addresses (fe80::/10) need a scope ID which URLs can't carry
+        let Some(address) = info
+            .get_addresses_v4()
+            .into_iter()
+            .next()
+            .map(|a| IpAddr::V4(*a))
+            .or_else(|| {
+                info.get_addresses()
+                    .iter()
+                    .find(|a| matches!(a, IpAddr::V6(v6) if v6.segments()[0] & 0xffc0 != 0xfe80))
+                    .copied()
+            })
+        else {
+            continue;
+        };
+        let Ok(url) = reqwest::Url::parse(&format!(
+            "https://{}/",
+            SocketAddr::new(address, info.get_port())
+        )) else {
+            continue;
+        };
+
+        tracing::debug!("Found {fullname} over mDNS");
+        found.insert(fullname.to_string());
+        hub.add_peer(url).await;
+    }
+}
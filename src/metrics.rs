@@ -0,0 +1,29 @@
+//! RSPI-BIOS Prometheus metrics
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::AppState;
+
+/// Handles `GET` requests to `/metrics`.
+///
+/// Returns the latest snapshot in the Prometheus text exposition format, every metric is a gauge.
+///
+/// See: <https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format>
+pub(crate) async fn metrics_handler(state: State<Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = state.snapshot_tx.borrow().clone();
+
+    let mut body = String::new();
+    if snapshot.timestamp != 0 {
+        for (metric, value) in snapshot.metrics() {
+            let _ = writeln!(body, "# TYPE rspi_bios_{metric} gauge");
+            let _ = writeln!(body, "rspi_bios_{metric} {value}");
+        }
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
@@ -0,0 +1,366 @@
+//! RSPI-BIOS MQTT publisher
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use rumqttc::{
+    AsyncClient, ClientError, Event, EventLoop, LastWill, MqttOptions, Packet, QoS,
+    TlsConfiguration, Transport,
+};
+use serde_json::json;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+use crate::snapshot::SystemSnapshot;
+
+/// CLI parameters for the optional MQTT publisher, flattened into `Args`.
+#[allow(clippy::struct_field_names)]
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct MqttArgs {
+    /// Hostname of the MQTT broker, enables the MQTT publisher when set
+    #[arg(long)]
+    pub(crate) mqtt_host: Option<String>,
+
+    #[arg(long, default_value_t = 1883)]
+    mqtt_port: u16,
+
+    /// Connect to the MQTT broker over TLS
+    #[arg(long)]
+    mqtt_tls: bool,
+
+    /// `.pem` CA bundle used to verify the MQTT broker's certificate
+    #[arg(long, default_value_os_t = PathBuf::from("/etc/ssl/certs/ca-certificates.crt"))]
+    mqtt_ca_file: PathBuf,
+
+    #[arg(long)]
+    mqtt_username: Option<String>,
+
+    /// File containing the MQTT password, kept out of the command line so it doesn't show up in `ps`. Needs
+    /// `--mqtt-username`
+    #[arg(long, requires = "mqtt_username")]
+    mqtt_password_file: Option<PathBuf>,
+
+    /// Defaults to `rspi-bios-<node id>`
+    #[arg(long)]
+    mqtt_client_id: Option<String>,
+
+    /// Defaults to the hostname
+    #[arg(long)]
+    mqtt_node_id: Option<String>,
+
+    #[arg(long, default_value = "rspi-bios")]
+    mqtt_topic_prefix: String,
+
+    #[arg(long, default_value = "homeassistant")]
+    mqtt_discovery_prefix: String,
+
+    /// Don't publish Home Assistant discovery configs
+    #[arg(long)]
+    mqtt_no_discovery: bool,
+
+    #[arg(long, value_parser = crate::parse_duration, default_value = "30")]
+    mqtt_keep_alive: Duration,
+
+    #[arg(long, value_parser = crate::parse_duration, default_value = "5")]
+    mqtt_reconnect_interval: Duration,
+}
+
+/// Home Assistant metadata for a metric from [`SystemSnapshot::metrics()`].
+struct Sensor {
+    name: &'static str,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+    state_class: &'static str,
+    icon: &'static str,
+}
+
+/// Returns the Home Assistant metadata used to describe the given metric.
+fn sensor(metric: &str) -> Sensor {
+    match metric {
+        "uptime" => Sensor {
+            name: "Uptime",
+            unit: Some("s"),
+            device_class: Some("duration"),
+            state_class: "total_increasing",
+            icon: "mdi:timer-outline",
+        },
+        "process_count" => Sensor {
+            name: "Process count",
+            unit: None,
+            device_class: None,
+            state_class: "measurement",
+            icon: "mdi:cogs",
+        },
+        "rx" => Sensor {
+            name: "Network RX",
+            unit: Some("B"),
+            device_class: Some("data_size"),
+            state_class: "total_increasing",
+            icon: "mdi:download-network",
+        },
+        "tx" => Sensor {
+            name: "Network TX",
+            unit: Some("B"),
+            device_class: Some("data_size"),
+            state_class: "total_increasing",
+            icon: "mdi:upload-network",
+        },
+        "cpu_usage" => Sensor {
+            name: "CPU usage",
+            unit: Some("%"),
+            device_class: None,
+            state_class: "measurement",
+            icon: "mdi:cpu-64-bit",
+        },
+        "used_memory" => Sensor {
+            name: "Memory used",
+            unit: Some("B"),
+            device_class: Some("data_size"),
+            state_class: "measurement",
+            icon: "mdi:memory",
+        },
+        "total_memory" => Sensor {
+            name: "Memory total",
+            unit: Some("B"),
+            device_class: Some("data_size"),
+            state_class: "measurement",
+            icon: "mdi:memory",
+        },
+        _ => Sensor {
+            name: "Unknown",
+            unit: None,
+            device_class: None,
+            state_class: "measurement",
+            icon: "mdi:help",
+        },
+    }
+}
+
+/// Topics and device information shared by every message sent to the broker.
+struct Publisher {
+    client: AsyncClient,
+    node_id: String,
+    topic_prefix: String,
+    discovery_prefix: Option<String>,
+    model_name: String,
+}
+
+impl Publisher {
+    fn state_topic(&self, metric: &str) -> String {
+        format!("{}/{}/{metric}", self.topic_prefix, self.node_id)
+    }
+
+    fn availability_topic(&self) -> String {
+        availability_topic(&self.topic_prefix, &self.node_id)
+    }
+
+    /// Publishes one retained Home Assistant discovery config per metric so sensors appear automatically.
+    ///
+    /// See: <https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery>
+    async fn publish_discovery(&self) -> Result<(), ClientError> {
+        let Some(discovery_prefix) = &self.discovery_prefix else {
+            return Ok(());
+        };
+
+        for (metric, _) in SystemSnapshot::default().metrics() {
+            let sensor = sensor(metric);
+            let config = json!({
+                "name": sensor.name,
+                "unique_id": format!("{}_{metric}", self.node_id),
+                "state_topic": self.state_topic(metric),
+                "availability_topic": self.availability_topic(),
+                "unit_of_measurement": sensor.unit,
+                "device_class": sensor.device_class,
+                "state_class": sensor.state_class,
+                "icon": sensor.icon,
+                "device": {
+                    "identifiers": [format!("rspi-bios_{}", self.node_id)],
+                    "name": self.node_id,
+                    "manufacturer": "Raspberry Pi",
+                    "model": self.model_name,
+                    "sw_version": env!("RSPI_BIOS_VERSION"),
+                },
+            });
+
+            let topic = format!("{discovery_prefix}/sensor/{}/{metric}/config", self.node_id);
+            self.client
+                .publish(topic, QoS::AtLeastOnce, true, config.to_string())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn publish_online(&self) -> Result<(), ClientError> {
+        self.client
+            .publish(self.availability_topic(), QoS::AtLeastOnce, true, "online")
+            .await
+    }
+
+    /// Publishes every metric in the snapshot to its own retained topic.
+    ///
+    /// Skips the placeholder snapshot sent before `send_system_messages()` has collected anything.
+    async fn publish_snapshot(&self, snapshot: &SystemSnapshot) -> Result<(), ClientError> {
+        if snapshot.timestamp == 0 {
+            return Ok(());
+        }
+
+        for (metric, value) in snapshot.metrics() {
+            self.client
+                .publish(
+                    self.state_topic(metric),
+                    QoS::AtMostOnce,
+                    true,
+                    value.to_string(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+fn availability_topic(topic_prefix: &str, node_id: &str) -> String {
+    format!("{topic_prefix}/{node_id}/availability")
+}
+
+/// Creates the MQTT client options from the CLI parameters.
+///
+/// Returns [`None`] if the CA bundle or password file can't be read.
+async fn create_mqtt_options(args: &MqttArgs, host: &str, node_id: &str) -> Option<MqttOptions> {
+    let client_id = args
+        .mqtt_client_id
+        .clone()
+        .unwrap_or_else(|| format!("rspi-bios-{node_id}"));
+
+    let mut options = MqttOptions::new(client_id, host, args.mqtt_port);
+    options.set_keep_alive(args.mqtt_keep_alive);
+    options.set_last_will(LastWill::new(
+        availability_topic(&args.mqtt_topic_prefix, node_id),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+
+    if let Some(username) = &args.mqtt_username {
+        let password = match &args.mqtt_password_file {
+            Some(path) => match tokio::fs::read_to_string(path).await {
+                Ok(p) => p.trim_end().to_string(),
+                Err(e) => {
+                    tracing::error!(error = %e, "Failed to read MQTT password from {}", path.display());
+                    return None;
+                }
+            },
+            None => String::new(),
+        };
+        options.set_credentials(username, password);
+    }
+
+    if args.mqtt_tls {
+        let ca = match tokio::fs::read(&args.mqtt_ca_file).await {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to read MQTT CA bundle from {}", args.mqtt_ca_file.display());
+                return None;
+            }
+        };
+        options.set_transport(Transport::tls_with_config(TlsConfiguration::Simple {
+            ca,
+            alpn: None,
+            client_auth: None,
+        }));
+    }
+
+    Some(options)
+}
+
+/// Publishes every [`SystemSnapshot`] sent by `send_system_messages()` to an MQTT broker.
+///
+/// Home Assistant discovery configs and an `online` availability message are (re)published every time a connection
+/// is made, the broker publishes `offline` via the last will if the connection drops.
+pub(crate) async fn publish_system_messages(
+    args: MqttArgs,
+    host: String,
+    model_name: String,
+    snapshot_rx: watch::Receiver<SystemSnapshot>,
+) {
+    let node_id = args
+        .mqtt_node_id
+        .clone()
+        .or_else(sysinfo::System::host_name)
+        .unwrap_or_else(|| "rspi-bios".to_string());
+
+    let Some(options) = create_mqtt_options(&args, &host, &node_id).await else {
+        tracing::error!("Failed to create MQTT options, MQTT publisher disabled");
+        return;
+    };
+
+    let (client, eventloop) = AsyncClient::new(options, 32);
+    let (connected_tx, connected_rx) = watch::channel(());
+
+    let publisher = Publisher {
+        client,
+        node_id,
+        topic_prefix: args.mqtt_topic_prefix,
+        discovery_prefix: (!args.mqtt_no_discovery).then_some(args.mqtt_discovery_prefix),
+        model_name,
+    };
+
+    tracing::info!("Connecting to MQTT broker at {host}:{}", args.mqtt_port);
+    tokio::spawn(publish_loop(publisher, connected_rx, snapshot_rx));
+
+    poll_eventloop(eventloop, connected_tx, args.mqtt_reconnect_interval).await;
+}
+
+/// Drives the MQTT connection, notifying `publish_loop()` every time a connection is (re)established.
+async fn poll_eventloop(
+    mut eventloop: EventLoop,
+    connected_tx: watch::Sender<()>,
+    reconnect_interval: Duration,
+) {
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                tracing::info!("Connected to MQTT broker");
+                connected_tx.send_replace(());
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(error = %e, "MQTT connection error, reconnecting in {reconnect_interval:?}");
+                sleep(reconnect_interval).await;
+            }
+        }
+    }
+}
+
+/// Publishes discovery configs after every (re)connection, and snapshots whenever they change.
+async fn publish_loop(
+    publisher: Publisher,
+    mut connected_rx: watch::Receiver<()>,
+    mut snapshot_rx: watch::Receiver<SystemSnapshot>,
+) {
+    loop {
+        let result = tokio::select! {
+            Ok(()) = connected_rx.changed() => {
+                let snapshot = snapshot_rx.borrow_and_update().clone();
+                async {
+                    publisher.publish_discovery().await?;
+                    publisher.publish_online().await?;
+                    publisher.publish_snapshot(&snapshot).await
+                }
+                .await
+            },
+            Ok(()) = snapshot_rx.changed() => {
+                let snapshot = snapshot_rx.borrow_and_update().clone();
+                publisher.publish_snapshot(&snapshot).await
+            },
+            else => return,
+        };
+
+        if let Err(e) = result {
+            tracing::warn!(error = %e, "Failed to publish MQTT message");
+        }
+    }
+}
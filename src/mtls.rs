@@ -0,0 +1,119 @@
+//! RSPI-BIOS mutual TLS
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Extension, Router,
+};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::WebPkiClientVerifier;
+use rustls::RootCertStore;
+
+use crate::listeners::TlsConnection;
+
+/// Groups of routes that can require a verified client certificate, health checks are always exempt so probes keep
+/// working.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ClientCertRoutes {
+    /// Every dashboard route e.g. `/`, `/sse`, `/static/*`, `/setup*`, `/admin/*`, `/hub*`, `/api/*`,
+    /// `/containers`, `/processes` and `/snapshot.png`
+    Dashboard,
+    /// `/metrics`
+    Metrics,
+}
+
+/// Loads the CA bundle used to verify client certificates.
+///
+/// Clients without a certificate are still accepted, they are rejected per route by [`require_client_cert`].
+/// Clients with a certificate not issued by one of the CAs fail the TLS handshake.
+///
+/// Returns [`None`] if the bundle can't be read or doesn't contain any certificates.
+pub(crate) async fn client_cert_verifier(ca_file: &Path) -> Option<Arc<dyn ClientCertVerifier>> {
+    let pem = match tokio::fs::read(ca_file).await {
+        Ok(p) => p,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to read client CA file {}", ca_file.display());
+            return None;
+        }
+    };
+
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+        let added = cert
+            .map_err(|e| e.to_string())
+            .and_then(|c| roots.add(c).map_err(|e| e.to_string()));
+        if let Err(e) = added {
+            tracing::error!(error = %e, "Invalid certificate in client CA file {}", ca_file.display());
+            return None;
+        }
+    }
+    if roots.is_empty() {
+        tracing::error!(
+            "No certificates found in client CA file {}",
+            ca_file.display()
+        );
+        return None;
+    }
+
+    match WebPkiClientVerifier::builder(Arc::new(roots))
+        .allow_unauthenticated()
+        .build()
+    {
+        Ok(v) => {
+            tracing::info!("Loaded client CA file {}", ca_file.display());
+            Some(v)
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to create client certificate verifier");
+            None
+        }
+    }
+}
+
+/// Returns the subject of the leaf client certificate e.g. `CN=grafana, O=Home`.
+///
+/// Only called after the handshake so the certificate has already been verified.
+pub(crate) fn client_subject(connection: &rustls::ServerConnection) -> Option<Arc<str>> {
+    let leaf = connection.peer_certificates()?.first()?;
+    match x509_parser::parse_x509_certificate(leaf) {
+        Ok((_, cert)) => Some(cert.subject().to_string().into()),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to parse client certificate");
+            None
+        }
+    }
+}
+
+/// Rejects requests that weren't made with a verified client certificate.
+async fn require_client_cert(
+    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    tls: Option<Extension<TlsConnection>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if tls.and_then(|Extension(t)| t.client_subject).is_none() {
+        tracing::warn!(uri = %request.uri(), "Rejected {addr}, no client certificate");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Enforces [`require_client_cert`] for the routes already added to `router` if `required` is `true`.
+pub(crate) fn enforce<S>(router: Router<S>, required: bool) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    if required {
+        router.route_layer(middleware::from_fn(require_client_cert))
+    } else {
+        router
+    }
+}
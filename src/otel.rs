@@ -0,0 +1,252 @@
+//! RSPI-BIOS OpenTelemetry export
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::time::Duration;
+
+use opentelemetry::metrics::{MeterProvider as _, ObservableGauge};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{
+    HttpExporterBuilder, MetricsExporterBuilder, SpanExporterBuilder, TonicExporterBuilder,
+    WithExportConfig,
+};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use sysinfo::System;
+use tokio::sync::watch;
+use tracing_opentelemetry::OpenTelemetryLayer;
+
+use crate::snapshot::{MetricValue, SystemSnapshot};
+
+/// OTLP transport.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OtlpProtocol {
+    /// OTLP/gRPC, plaintext only e.g. to a collector on the same host
+    Grpc,
+    /// OTLP/HTTP with protobuf, supports `https://`
+    Http,
+}
+
+/// CLI parameters for the optional OpenTelemetry export, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct OtelArgs {
+    /// OTLP collector endpoint, enables exporting spans when set
+    /// e.g. `http://localhost:4317` (gRPC) or `http://localhost:4318` (HTTP)
+    #[arg(long)]
+    otlp_endpoint: Option<reqwest::Url>,
+
+    #[arg(long, value_enum, default_value_t = OtlpProtocol::Grpc)]
+    otlp_protocol: OtlpProtocol,
+
+    /// Also export system information as OTLP metrics
+    #[arg(long)]
+    otlp_metrics: bool,
+
+    #[arg(long, value_parser = crate::parse_duration, default_value = "10")]
+    otlp_metrics_interval: Duration,
+
+    #[arg(long, default_value = "rspi-bios")]
+    otel_service_name: String,
+}
+
+/// OpenTelemetry providers, spans come from the [`tracing`] subscriber and metrics from [`SystemSnapshot`]s.
+pub(crate) struct Telemetry {
+    tracer_provider: TracerProvider,
+    meter_provider: Option<SdkMeterProvider>,
+    /// Callbacks are only run while their instrument is alive
+    integer_gauges: Vec<ObservableGauge<u64>>,
+    float_gauges: Vec<ObservableGauge<f64>>,
+}
+
+/// Semantic convention attributes describing this Pi, `OTEL_RESOURCE_ATTRIBUTES` is also read.
+///
+/// See: <https://opentelemetry.io/docs/specs/semconv/resource/>
+fn resource(service_name: &str, model_name: &str) -> Resource {
+    let mut attributes = vec![
+        KeyValue::new("service.name", service_name.to_string()),
+        KeyValue::new("service.version", env!("RSPI_BIOS_VERSION")),
+        KeyValue::new("device.model.name", model_name.to_string()),
+        KeyValue::new("os.type", std::env::consts::OS),
+    ];
+    if let Some(host_name) = System::host_name() {
+        attributes.push(KeyValue::new("host.name", host_name));
+    }
+    if let Some(cpu_arch) = System::cpu_arch() {
+        attributes.push(KeyValue::new("host.arch", cpu_arch));
+    }
+    if let Some(os_version) = System::long_os_version() {
+        attributes.push(KeyValue::new("os.description", os_version));
+    }
+
+    Resource::default().merge(&Resource::new(attributes))
+}
+
+/// Creates a span or metrics exporter builder, OTLP/HTTP endpoints need the signal path but gRPC only needs the
+/// base URL.
+fn exporter<T>(args: &OtelArgs, endpoint: &reqwest::Url, signal_path: &str) -> Result<T, String>
+where
+    T: From<TonicExporterBuilder> + From<HttpExporterBuilder>,
+{
+    match args.otlp_protocol {
+        OtlpProtocol::Grpc => {
+            if endpoint.scheme() != "http" {
+                return Err(
+                    "the gRPC exporter only supports `http://`, use `--otlp-protocol http` for TLS"
+                        .to_string(),
+                );
+            }
+            Ok(opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint.as_str())
+                .into())
+        }
+        OtlpProtocol::Http => {
+            let endpoint = endpoint.join(signal_path).map_err(|e| e.to_string())?;
+            Ok(opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint.as_str())
+                .into())
+        }
+    }
+}
+
+impl Telemetry {
+    /// Sets up the OTLP exporters, has to be called inside the [`tokio`] runtime.
+    ///
+    /// Returns `Ok(None)` if `--otlp-endpoint` isn't set. Logging isn't set up yet so errors are returned.
+    pub(crate) fn new(args: &OtelArgs, model_name: &str) -> Result<Option<Self>, String> {
+        let Some(endpoint) = &args.otlp_endpoint else {
+            return Ok(None);
+        };
+        let resource = resource(&args.otel_service_name, model_name);
+
+        let tracer_provider = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(exporter::<SpanExporterBuilder>(
+                args,
+                endpoint,
+                "v1/traces",
+            )?)
+            .with_trace_config(
+                opentelemetry_sdk::trace::Config::default().with_resource(resource.clone()),
+            )
+            .install_batch(runtime::Tokio)
+            .map_err(|e| e.to_string())?;
+
+        let meter_provider = if args.otlp_metrics {
+            Some(
+                opentelemetry_otlp::new_pipeline()
+                    .metrics(runtime::Tokio)
+                    .with_exporter(exporter::<MetricsExporterBuilder>(
+                        args,
+                        endpoint,
+                        "v1/metrics",
+                    )?)
+                    .with_resource(resource)
+                    .with_period(args.otlp_metrics_interval)
+                    .build()
+                    .map_err(|e| e.to_string())?,
+            )
+        } else {
+            None
+        };
+
+        // Export failures e.g. collector down, would be printed to STDERR otherwise
+        let _ = opentelemetry::global::set_error_handler(|e| {
+            tracing::warn!(error = %e, "OpenTelemetry error");
+        });
+
+        Ok(Some(Self {
+            tracer_provider,
+            meter_provider,
+            integer_gauges: Vec::new(),
+            float_gauges: Vec::new(),
+        }))
+    }
+
+    /// [`tracing`] layer exporting spans e.g. `request` spans from `TraceLayer`.
+    pub(crate) fn layer<S>(&self) -> OpenTelemetryLayer<S, Tracer>
+    where
+        S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.tracer_provider.tracer("rspi-bios"))
+    }
+
+    /// Exports the latest [`SystemSnapshot`] metrics as gauges named `rspi_bios.<metric>`, no-op unless
+    /// `--otlp-metrics` is set.
+    pub(crate) fn observe_snapshots(&mut self, snapshot_rx: &watch::Receiver<SystemSnapshot>) {
+        let Some(meter_provider) = &self.meter_provider else {
+            return;
+        };
+        let meter = meter_provider.meter("rspi-bios");
+
+        for (metric, value) in SystemSnapshot::default().metrics() {
+            let name = format!("rspi_bios.{metric}");
+            let unit = unit(metric);
+            let snapshot_rx = snapshot_rx.clone();
+            // Nothing is observed until the first snapshot has been collected
+            let latest = move || {
+                let snapshot = snapshot_rx.borrow();
+                if snapshot.timestamp == 0 {
+                    return None;
+                }
+                snapshot
+                    .metrics()
+                    .into_iter()
+                    .find_map(|(m, v)| (m == metric).then_some(v))
+            };
+
+            match value {
+                MetricValue::Integer(_) => self.integer_gauges.push(
+                    meter
+                        .u64_observable_gauge(name)
+                        .with_unit(unit)
+                        .with_callback(move |observer| {
+                            if let Some(MetricValue::Integer(v)) = latest() {
+                                observer.observe(v, &[]);
+                            }
+                        })
+                        .init(),
+                ),
+                MetricValue::Float(_) => self.float_gauges.push(
+                    meter
+                        .f64_observable_gauge(name)
+                        .with_unit(unit)
+                        .with_callback(move |observer| {
+                            if let Some(MetricValue::Float(v)) = latest() {
+                                observer.observe(f64::from(v), &[]);
+                            }
+                        })
+                        .init(),
+                ),
+            }
+        }
+    }
+
+    /// Flushes anything that hasn't been exported yet.
+    pub(crate) fn shutdown(self) {
+        drop(self.integer_gauges);
+        drop(self.float_gauges);
+        if let Err(e) = self.tracer_provider.shutdown() {
+            tracing::debug!(error = %e, "Failed to shut down OpenTelemetry tracer provider");
+        }
+        if let Some(meter_provider) = self.meter_provider {
+            if let Err(e) = meter_provider.shutdown() {
+                tracing::debug!(error = %e, "Failed to shut down OpenTelemetry meter provider");
+            }
+        }
+    }
+}
+
+/// UCUM unit of each [`SystemSnapshot`] metric.
+fn unit(metric: &str) -> &'static str {
+    match metric {
+        "uptime" => "s",
+        "rx" | "tx" | "used_memory" | "total_memory" => "By",
+        "cpu_usage" => "%",
+        "process_count" => "{process}",
+        _ => "1",
+    }
+}
@@ -0,0 +1,333 @@
+//! RSPI-BIOS power-on self-test
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::response::sse::Event;
+use serde::Serialize;
+use sysinfo::{Components, Disks};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::units;
+use crate::AppState;
+
+/// How long an external command, a D-Bus call or the default gateway probe may take.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long results are shared with other clients, so reconnecting in a loop can't keep querying systemd and
+/// `timedatectl`.
+const RESULTS_TTL: Duration = Duration::from_secs(15);
+
+/// CLI parameters for the checks shown on the boot screen, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct PostArgs {
+    /// Free space on the root filesystem in percent below which the POST fails
+    #[arg(long, default_value_t = 10)]
+    post_min_free_disk: u64,
+
+    /// Temperature in °C above which the POST fails
+    #[arg(long, default_value_t = 80.0)]
+    post_max_temperature: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Ok,
+    Fail,
+    /// Couldn't be checked on this system e.g. no `timedatectl`
+    Skipped,
+}
+
+/// Sent as a `post` SSE event.
+#[derive(Clone, Serialize)]
+pub(crate) struct CheckResult {
+    name: &'static str,
+    status: Status,
+    detail: String,
+}
+
+/// Checks in the order they are shown.
+#[derive(Clone, Copy)]
+enum Check {
+    Filesystem,
+    DiskSpace,
+    Temperature,
+    Ntp,
+    DefaultRoute,
+    SystemdUnits,
+}
+
+impl Check {
+    const ALL: [Self; 6] = [
+        Self::Filesystem,
+        Self::DiskSpace,
+        Self::Temperature,
+        Self::Ntp,
+        Self::DefaultRoute,
+        Self::SystemdUnits,
+    ];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Filesystem => "Root Filesystem",
+            Self::DiskSpace => "Disk Space",
+            Self::Temperature => "Temperature",
+            Self::Ntp => "NTP Synchronization",
+            Self::DefaultRoute => "Default Gateway",
+            Self::SystemdUnits => "Systemd Units",
+        }
+    }
+
+    async fn run(self, args: &PostArgs) -> (Status, String) {
+        match self {
+            Self::Filesystem => filesystem_check().await,
+            Self::DiskSpace => disk_space_check(args.post_min_free_disk),
+            Self::Temperature => temperature_check(args.post_max_temperature),
+            Self::Ntp => ntp_check().await,
+            Self::DefaultRoute => default_route_check().await,
+            Self::SystemdUnits => systemd_units_check().await,
+        }
+    }
+}
+
+/// Results of the last run, shared by every client for [`RESULTS_TTL`].
+#[derive(Default)]
+pub(crate) struct PostResults(Mutex<Option<(Instant, Vec<CheckResult>)>>);
+
+/// Sends `result` as a `post` event, returns `false` if the client went away.
+async fn send(tx: &mpsc::Sender<Event>, result: &CheckResult) -> bool {
+    match Event::default().event("post").json_data(result) {
+        Ok(event) => tx.send(event).await.is_ok(),
+        Err(_) => true,
+    }
+}
+
+/// Runs every check in order, sending each result as a `post` event and the number of failures as a `post-end`
+/// event once done.
+///
+/// Only one run happens at a time, clients connecting meanwhile wait for it and get its results like every client
+/// within [`RESULTS_TTL`].
+pub(crate) async fn run(args: PostArgs, state: Arc<AppState>, tx: mpsc::Sender<Event>) {
+    let mut cached = state.post_results.0.lock().await;
+
+    let results = match &*cached {
+        Some((ran_at, results)) if ran_at.elapsed() < RESULTS_TTL => {
+            for result in results {
+                if !send(&tx, result).await {
+                    return;
+                }
+            }
+            results.clone()
+        }
+        _ => {
+            // Finished even if the client goes away so the results can be reused
+            let mut connected = true;
+            let mut results = Vec::with_capacity(Check::ALL.len());
+            for check in Check::ALL {
+                let (status, detail) = check.run(&args).await;
+                if status == Status::Fail {
+                    tracing::warn!(check = check.name(), detail, "POST check failed");
+                }
+
+                let result = CheckResult {
+                    name: check.name(),
+                    status,
+                    detail,
+                };
+                connected = connected && send(&tx, &result).await;
+                results.push(result);
+            }
+            *cached = Some((Instant::now(), results.clone()));
+            results
+        }
+    };
+    drop(cached);
+
+    let failed = results.iter().filter(|r| r.status == Status::Fail).count();
+    let _ = tx
+        .send(Event::default().event("post-end").data(failed.to_string()))
+        .await;
+}
+
+/// Runs `program`, returns its trimmed standard output if it succeeded within [`CHECK_TIMEOUT`].
+async fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = tokio::time::timeout(
+        CHECK_TIMEOUT,
+        Command::new(program).args(args).kill_on_drop(true).output(),
+    )
+    .await
+    .ok()?
+    .ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The root filesystem is remounted read-only by the kernel after SD card errors.
+async fn filesystem_check() -> (Status, String) {
+    let Ok(mounts) = tokio::fs::read_to_string("/proc/self/mounts").await else {
+        return (Status::Skipped, "/proc/self/mounts unavailable".to_string());
+    };
+
+    // Later mounts hide earlier ones on the same mount point
+    let root_options = mounts.lines().rev().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        (fields.nth(1) == Some("/"))
+            .then(|| fields.nth(1))
+            .flatten()
+    });
+
+    match root_options {
+        Some(options) if options.split(',').any(|o| o == "rw") => {
+            (Status::Ok, "read-write".to_string())
+        }
+        Some(_) => (Status::Fail, "read-only".to_string()),
+        None => (Status::Skipped, "no root mount".to_string()),
+    }
+}
+
+fn disk_space_check(min_free_percent: u64) -> (Status, String) {
+    let disks = Disks::new_with_refreshed_list();
+    let Some(disk) = disks
+        .iter()
+        .find(|d| d.mount_point() == std::path::Path::new("/"))
+        .or_else(|| disks.first())
+        .filter(|d| d.total_space() > 0)
+    else {
+        return (Status::Skipped, "no disks".to_string());
+    };
+
+    let free_percent = disk.available_space() * 100 / disk.total_space();
+    let status = if free_percent >= min_free_percent {
+        Status::Ok
+    } else {
+        Status::Fail
+    };
+    (status, format!("{free_percent}% free"))
+}
+
+/// Uses the hottest sensor.
+fn temperature_check(max_temperature: f32) -> (Status, String) {
+    let Some(temperature) = Components::new_with_refreshed_list()
+        .iter()
+        .map(sysinfo::Component::temperature)
+        .filter(|t| t.is_finite())
+        .reduce(f32::max)
+    else {
+        return (Status::Skipped, "no sensors".to_string());
+    };
+
+    let status = if temperature <= max_temperature {
+        Status::Ok
+    } else {
+        Status::Fail
+    };
+    (status, format!("{temperature:.1}\u{b0}C"))
+}
+
+/// Asks `systemd-timedated`, which knows about `systemd-timesyncd`, `chrony` and `ntpd`.
+async fn ntp_check() -> (Status, String) {
+    match command_output(
+        "timedatectl",
+        &["show", "--property=NTPSynchronized", "--value"],
+    )
+    .await
+    .as_deref()
+    {
+        Some("yes") => (Status::Ok, "synchronized".to_string()),
+        Some(_) => (Status::Fail, "not synchronized".to_string()),
+        None => (Status::Skipped, "timedatectl unavailable".to_string()),
+    }
+}
+
+/// Finds the IPv4 default gateway and its interface in the contents of `/proc/net/route`.
+fn default_gateway(routes: &str) -> Option<(Ipv4Addr, String)> {
+    // Iface Destination Gateway Flags RefCnt Use Metric Mask ..., addresses are little-endian hex
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [iface, "00000000", gateway, _, _, _, _, "00000000", ..] => {
+                let gateway = u32::from_str_radix(gateway, 16).ok()?;
+                Some((Ipv4Addr::from(gateway.to_le_bytes()), (*iface).to_string()))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Returns `true` if the contents of `/proc/net/arp` have a resolved entry for `address` on `iface`.
+fn has_neighbour(arp: &str, address: Ipv4Addr, iface: &str) -> bool {
+    // IP address, HW type, Flags, HW address, Mask, Device. Flag 0x2 is ATF_COM, the MAC address is known
+    arp.lines().skip(1).any(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        matches!(
+            fields.as_slice(),
+            [ip, _, flags, _, _, device, ..]
+                if ip.parse() == Ok(address)
+                    && *device == iface
+                    && u32::from_str_radix(flags.trim_start_matches("0x"), 16).is_ok_and(|f| f & 0x2 != 0)
+        )
+    })
+}
+
+/// The gateway counts as reachable if it accepts or refuses a TCP connection to its DNS port, raw sockets for ping
+/// need extra privileges.
+///
+/// Routers that drop the connection instead make the probe time out, the gateway still counts as reachable if the
+/// probe resolved its MAC address. Otherwise the result is inconclusive rather than a failure.
+async fn default_route_check() -> (Status, String) {
+    let Ok(routes) = tokio::fs::read_to_string("/proc/net/route").await else {
+        return (Status::Skipped, "/proc/net/route unavailable".to_string());
+    };
+    let Some((gateway, iface)) = default_gateway(&routes) else {
+        return (Status::Fail, "no default route".to_string());
+    };
+    // Point-to-point links e.g. WireGuard or PPP don't have a gateway address
+    if gateway.is_unspecified() {
+        return (Status::Ok, format!("via {iface}"));
+    }
+
+    let probe = tokio::time::timeout(
+        CHECK_TIMEOUT,
+        TcpStream::connect(SocketAddr::from((gateway, 53))),
+    )
+    .await;
+    match probe {
+        Ok(Ok(_)) => (Status::Ok, format!("{gateway} via {iface}")),
+        Ok(Err(e)) if e.kind() == ErrorKind::ConnectionRefused => {
+            (Status::Ok, format!("{gateway} via {iface}"))
+        }
+        Ok(Err(_)) => (Status::Fail, format!("{gateway} unreachable")),
+        Err(_) => match tokio::fs::read_to_string("/proc/net/arp").await {
+            Ok(arp) if has_neighbour(&arp, gateway, &iface) => {
+                (Status::Ok, format!("{gateway} via {iface}"))
+            }
+            _ => (Status::Skipped, format!("no reply from {gateway}")),
+        },
+    }
+}
+
+async fn systemd_units_check() -> (Status, String) {
+    let failed = tokio::time::timeout(CHECK_TIMEOUT, async {
+        units::failed_units(&zbus::Connection::system().await?).await
+    })
+    .await;
+    let Ok(Ok(failed)) = failed else {
+        return (Status::Skipped, "systemd unavailable".to_string());
+    };
+
+    if failed.is_empty() {
+        (Status::Ok, "none failed".to_string())
+    } else {
+        (Status::Fail, format!("failed: {}", failed.join(", ")))
+    }
+}
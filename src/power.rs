@@ -0,0 +1,362 @@
+//! RSPI-BIOS power actions
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::io::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    middleware,
+    response::{IntoResponse, Redirect, Response},
+    routing::post,
+    Form, Router,
+};
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+use crate::log_file::{LogRotationArgs, RotatingFile};
+use crate::{admin, setup, AppState};
+
+/// CLI parameters for power actions, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct PowerArgs {
+    /// Allow rebooting, powering off and restarting `--power-unit`s through `/admin/power/*` and SETUP, needs
+    /// `--admin-token-file`
+    #[arg(long)]
+    enable_power_actions: bool,
+
+    /// systemd unit that can be restarted e.g. `nginx.service`, can be repeated
+    #[arg(long = "power-unit")]
+    power_units: Vec<String>,
+
+    /// Run `<COMMAND> reboot`, `<COMMAND> poweroff` or `<COMMAND> restart <UNIT>` instead of calling logind/systemd
+    /// over D-Bus e.g. `sudo /usr/local/sbin/rspi-power`
+    #[arg(long)]
+    power_command: Option<String>,
+
+    /// Only write the audit log instead of acting, for testing
+    #[arg(long)]
+    power_dry_run: bool,
+}
+
+/// Action requested through `/admin/power/:action` or `/setup/power/:action`.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PowerAction {
+    Reboot,
+    Poweroff,
+    /// Restarts one of the `--power-unit`s
+    Restart,
+}
+
+impl PowerAction {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Reboot => "reboot",
+            Self::Poweroff => "poweroff",
+            Self::Restart => "restart",
+        }
+    }
+
+    /// Shown in SETUP once the action was requested.
+    pub(crate) const fn label(self) -> &'static str {
+        match self {
+            Self::Reboot => "System Reboot",
+            Self::Poweroff => "System Power Off",
+            Self::Restart => "Service Restart",
+        }
+    }
+}
+
+/// Reboots, powers off or restarts units, every attempt is written to `audit.log`.
+pub(crate) struct Power {
+    units: Vec<String>,
+    /// Program followed by its arguments
+    command: Option<Vec<String>>,
+    dry_run: bool,
+    audit_log: Option<Mutex<RotatingFile>>,
+}
+
+impl Power {
+    /// Returns [`None`] if `--enable-power-actions` isn't set.
+    ///
+    /// `audit_log_path` is placed next to `debug.log`, actions are still allowed if it can't be opened since they are
+    /// also logged there.
+    pub(crate) fn new(
+        args: &PowerArgs,
+        admin_enabled: bool,
+        audit_log_path: &std::path::Path,
+        rotation: LogRotationArgs,
+    ) -> Result<Option<Self>, String> {
+        if !args.enable_power_actions {
+            return Ok(None);
+        }
+        if !admin_enabled {
+            return Err("--enable-power-actions needs --admin-token-file".to_string());
+        }
+        let command = match &args.power_command {
+            Some(c) if c.trim().is_empty() => return Err("--power-command is empty".to_string()),
+            Some(c) => Some(c.split_whitespace().map(str::to_string).collect()),
+            None => None,
+        };
+
+        let audit_log = match RotatingFile::open(audit_log_path.to_path_buf(), rotation) {
+            Ok(f) => {
+                tracing::info!("Writing power action audit log to {audit_log_path:?}");
+                Some(Mutex::new(f))
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to open/create {audit_log_path:?}, did you set the correct permissions?");
+                None
+            }
+        };
+        if args.power_dry_run {
+            tracing::warn!(
+                "Power actions are in dry-run mode, they are only written to the audit log"
+            );
+        }
+
+        Ok(Some(Self {
+            units: args.power_units.clone(),
+            command,
+            dry_run: args.power_dry_run,
+            audit_log,
+        }))
+    }
+
+    /// Units that can be restarted, for the SETUP screen.
+    pub(crate) fn units(&self) -> &[String] {
+        &self.units
+    }
+
+    pub(crate) const fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    async fn run(&self, action: PowerAction, unit: Option<&str>) -> Result<(), String> {
+        if self.dry_run {
+            return Ok(());
+        }
+        match &self.command {
+            Some(command) => run_command(command, action, unit).await,
+            None => call_dbus(action, unit).await.map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Writes a JSON line to `audit.log` and the debug log.
+    fn audit(
+        &self,
+        addr: SocketAddr,
+        auth: &str,
+        action: PowerAction,
+        unit: Option<&str>,
+        result: &Result<(), String>,
+    ) {
+        match result {
+            Ok(()) => tracing::warn!(
+                dry_run = self.dry_run,
+                "Power action {} {} requested by {addr} ({auth})",
+                action.as_str(),
+                unit.unwrap_or_default()
+            ),
+            Err(e) => tracing::error!(
+                error = %e,
+                "Power action {} {} requested by {addr} ({auth}) failed",
+                action.as_str(),
+                unit.unwrap_or_default()
+            ),
+        }
+
+        let Some(Ok(mut file)) = self.audit_log.as_ref().map(Mutex::lock) else {
+            return;
+        };
+        let line = serde_json::json!({
+            "time": OffsetDateTime::now_utc()
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_default(),
+            "remote_addr": addr.ip().to_string(),
+            "auth": auth,
+            "action": action.as_str(),
+            "unit": unit,
+            "dry_run": self.dry_run,
+            "error": result.as_ref().err(),
+        });
+        if let Err(e) = file.write_all(format!("{line}\n").as_bytes()) {
+            tracing::warn!(error = %e, "Failed to write to audit log");
+        }
+    }
+}
+
+async fn run_command(
+    command: &[String],
+    action: PowerAction,
+    unit: Option<&str>,
+) -> Result<(), String> {
+    let Some((program, args)) = command.split_first() else {
+        return Err("--power-command is empty".to_string());
+    };
+    let status = tokio::process::Command::new(program)
+        .args(args)
+        .arg(action.as_str())
+        .args(unit)
+        .status()
+        .await
+        .map_err(|e| format!("Failed to run {program}. Error: {e}"))?;
+    if !status.success() {
+        return Err(format!("{program} exited with {status}"));
+    }
+    Ok(())
+}
+
+/// Asks logind to reboot/power off or systemd to restart `unit`, both check polkit for the user running this.
+async fn call_dbus(action: PowerAction, unit: Option<&str>) -> zbus::Result<()> {
+    let connection = zbus::Connection::system().await?;
+    match (action, unit) {
+        (PowerAction::Restart, Some(unit)) => {
+            connection
+                .call_method(
+                    Some("org.freedesktop.systemd1"),
+                    "/org/freedesktop/systemd1",
+                    Some("org.freedesktop.systemd1.Manager"),
+                    "RestartUnit",
+                    &(unit, "replace"),
+                )
+                .await?;
+        }
+        (PowerAction::Restart, None) => {
+            return Err(zbus::Error::Failure("No unit to restart".to_string()));
+        }
+        (PowerAction::Reboot | PowerAction::Poweroff, _) => {
+            connection
+                .call_method(
+                    Some("org.freedesktop.login1"),
+                    "/org/freedesktop/login1",
+                    Some("org.freedesktop.login1.Manager"),
+                    if action == PowerAction::Reboot {
+                        "Reboot"
+                    } else {
+                        "PowerOff"
+                    },
+                    // Not interactive, polkit can't ask for a password
+                    &(false,),
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Body of `POST /admin/power/:action` and `/setup/power/:action` requests.
+#[derive(Deserialize)]
+pub(crate) struct PowerForm {
+    /// Required for `restart`, one of `--power-unit`
+    unit: Option<String>,
+    /// Has to be `Y` so a stray request can't reboot the Pi
+    #[serde(default)]
+    confirm: String,
+    /// Only checked for SETUP, see [`admin::Sessions::csrf_matches`]
+    #[serde(default)]
+    csrf_token: String,
+}
+
+/// Validates and runs `action`.
+///
+/// Returns `404 Not Found` if power actions are disabled, `400 Bad Request` if it isn't confirmed or the unit isn't
+/// allowed and `500 Internal Server Error` if it failed.
+async fn perform(
+    state: &AppState,
+    addr: SocketAddr,
+    auth: &str,
+    action: PowerAction,
+    form: &PowerForm,
+) -> Result<(), Response> {
+    let Some(power) = &state.power else {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
+    if form.confirm != "Y" {
+        return Err((StatusCode::BAD_REQUEST, "Confirm with confirm=Y").into_response());
+    }
+    let unit = match action {
+        PowerAction::Restart => match form.unit.as_deref() {
+            Some(u) if power.units.iter().any(|allowed| allowed == u) => Some(u),
+            _ => {
+                return Err(
+                    (StatusCode::BAD_REQUEST, "Unit isn't one of --power-unit").into_response()
+                )
+            }
+        },
+        PowerAction::Reboot | PowerAction::Poweroff => None,
+    };
+
+    let result = power.run(action, unit).await;
+    power.audit(addr, auth, action, unit, &result);
+    result.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to {}, see the log", action.as_str()),
+        )
+            .into_response()
+    })
+}
+
+/// Handles `POST` requests to `/admin/power/:action`.
+///
+/// Returns `202 Accepted` once logind/systemd or the command accepted the action.
+async fn admin_handler(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(action): Path<PowerAction>,
+    state: State<Arc<AppState>>,
+    Form(form): Form<PowerForm>,
+) -> Response {
+    match perform(&state, addr, "admin token", action, &form).await {
+        Ok(()) if state.power.as_ref().is_some_and(Power::dry_run) => {
+            (StatusCode::ACCEPTED, "Dry run, nothing was done").into_response()
+        }
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Handles `POST` requests to `/setup/power/:action` from the "Exit Options" dialogs, goes back to SETUP.
+///
+/// Returns `403 Forbidden` if the CSRF token doesn't match the session.
+async fn setup_handler(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(action): Path<PowerAction>,
+    headers: HeaderMap,
+    state: State<Arc<AppState>>,
+    Form(form): Form<PowerForm>,
+) -> Response {
+    if !state.sessions.csrf_matches(&headers, &form.csrf_token) {
+        tracing::warn!("Rejected power action with an invalid CSRF token from {addr}");
+        return (StatusCode::FORBIDDEN, "Invalid CSRF token, reload SETUP").into_response();
+    }
+
+    match perform(&state, addr, "SETUP session", action, &form).await {
+        Ok(()) => {
+            Redirect::to(&format!("../../setup?power={}#exit", action.as_str())).into_response()
+        }
+        Err(response) => response,
+    }
+}
+
+/// Creates `/admin/power/:action` (admin token) and `/setup/power/:action` (SETUP session).
+pub(crate) fn power_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/admin/power/:action", post(admin_handler))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            admin::require_admin_token,
+        ))
+        .merge(
+            Router::new()
+                .route("/setup/power/:action", post(setup_handler))
+                .route_layer(middleware::from_fn_with_state(
+                    state,
+                    setup::require_session,
+                )),
+        )
+}
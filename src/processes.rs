@@ -0,0 +1,282 @@
+//! RSPI-BIOS top processes
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::HeaderMap,
+    response::Response,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use sysinfo::{Process, System, Uid, Users};
+use time::OffsetDateTime;
+
+use crate::security::CspNonce;
+use crate::theme::SelectedTheme;
+use crate::AppState;
+
+/// Command lines longer than this are cut off.
+const COMMAND_LENGTH: usize = 80;
+
+/// CLI parameters for the process table, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct ProcessesArgs {
+    /// Number of processes using the most CPU and memory to list in `/processes`, `/api/processes` and `processes`
+    /// SSE events, `0` to disable. Exposes their command lines, which can contain secrets passed as arguments, to
+    /// anyone who can open the dashboard
+    #[arg(long, default_value_t = 0)]
+    top_processes: usize,
+}
+
+impl ProcessesArgs {
+    pub(crate) const fn enabled(&self) -> bool {
+        self.top_processes > 0
+    }
+}
+
+/// A process in [`TopProcesses`].
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct ProcessInfo {
+    pub(crate) pid: u32,
+    pub(crate) name: String,
+    /// User name, the user ID if it has none
+    pub(crate) user: String,
+    /// CPU usage in percent, 100 per core
+    pub(crate) cpu_usage: f32,
+    /// Resident set size in bytes
+    pub(crate) memory: u64,
+    /// Seconds since the Unix epoch
+    pub(crate) start_time: u64,
+    /// Cut off after [`COMMAND_LENGTH`] characters, `[name]` for kernel threads like `ps`
+    pub(crate) command: String,
+}
+
+/// Fields of a [`Process`] copied while `AppState::system` is locked, turned into a [`ProcessInfo`] after.
+pub(crate) struct ProcessSample {
+    pid: u32,
+    name: String,
+    user_id: Option<Uid>,
+    cpu_usage: f32,
+    memory: u64,
+    start_time: u64,
+    cmd: Vec<String>,
+}
+
+impl ProcessSample {
+    fn new(process: &Process) -> Self {
+        Self {
+            pid: process.pid().as_u32(),
+            name: process.name().to_string_lossy().into_owned(),
+            user_id: process.user_id().cloned(),
+            cpu_usage: process.cpu_usage(),
+            memory: process.memory(),
+            start_time: process.start_time(),
+            cmd: process
+                .cmd()
+                .iter()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect(),
+        }
+    }
+}
+
+/// User names of processes.
+pub(crate) struct UserNames {
+    users: Users,
+    /// Unknown users the list was refreshed for, so it is only read again for new ones
+    refreshed_for: HashSet<Uid>,
+}
+
+impl UserNames {
+    /// Empty until the first lookup, which refreshes the list.
+    pub(crate) fn new() -> Self {
+        Self {
+            users: Users::new(),
+            refreshed_for: HashSet::new(),
+        }
+    }
+
+    /// Returns the user ID if the user has no name, e.g. in a container with its own user namespace.
+    fn get(&mut self, uid: &Uid) -> String {
+        if self.users.get_user_by_id(uid).is_none() && self.refreshed_for.insert(uid.clone()) {
+            self.users.refresh_list();
+        }
+        self.users
+            .get_user_by_id(uid)
+            .map_or_else(|| uid.to_string(), |u| u.name().to_string())
+    }
+}
+
+impl ProcessInfo {
+    fn new(sample: &ProcessSample, users: &mut UserNames) -> Self {
+        let command = sample.cmd.join(" ");
+        let command = if command.is_empty() {
+            format!("[{}]", sample.name)
+        } else if command.chars().count() > COMMAND_LENGTH {
+            let mut truncated: String = command.chars().take(COMMAND_LENGTH - 3).collect();
+            truncated.push_str("...");
+            truncated
+        } else {
+            command
+        };
+
+        Self {
+            pid: sample.pid,
+            name: sample.name.clone(),
+            user: sample
+                .user_id
+                .as_ref()
+                .map(|uid| users.get(uid))
+                .unwrap_or_default(),
+            cpu_usage: sample.cpu_usage,
+            memory: sample.memory,
+            start_time: sample.start_time,
+            command,
+        }
+    }
+
+    /// Start time as shown in the table.
+    pub(crate) fn started(&self) -> String {
+        OffsetDateTime::from_unix_timestamp(i64::try_from(self.start_time).unwrap_or_default())
+            .ok()
+            .and_then(|t| {
+                t.format(time::macros::format_description!(
+                    "[month]-[day] [hour]:[minute]"
+                ))
+                .ok()
+            })
+            .unwrap_or_else(|| "-".to_string())
+    }
+
+    pub(crate) fn cpu_text(&self) -> String {
+        format!("{:.1}%", self.cpu_usage)
+    }
+
+    pub(crate) fn memory_text(&self) -> String {
+        format!("{}MB", self.memory / 1_048_576)
+    }
+}
+
+/// Processes using the most CPU and memory, sent in `processes` SSE events and by `GET /api/processes`.
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct TopProcesses {
+    pub(crate) by_cpu: Vec<ProcessInfo>,
+    pub(crate) by_memory: Vec<ProcessInfo>,
+}
+
+/// Top processes picked while `AppState::system` is locked, see [`TopProcesses::sample()`].
+pub(crate) struct TopSample {
+    by_cpu: Vec<ProcessSample>,
+    by_memory: Vec<ProcessSample>,
+}
+
+impl TopProcesses {
+    /// Picks the top `args.top_processes` from `system`, which `SystemSnapshot::collect()` just refreshed.
+    ///
+    /// Only copies what [`TopProcesses::collect()`] needs so the lock can be released before users are looked up.
+    pub(crate) fn sample(args: &ProcessesArgs, system: &System) -> TopSample {
+        if !args.enabled() {
+            return TopSample {
+                by_cpu: Vec::new(),
+                by_memory: Vec::new(),
+            };
+        }
+
+        // Threads share the memory of their process, they would show up as duplicates
+        let mut processes: Vec<&Process> = system
+            .processes()
+            .values()
+            .filter(|p| p.thread_kind().is_none())
+            .collect();
+
+        processes.sort_unstable_by(|a, b| b.cpu_usage().total_cmp(&a.cpu_usage()));
+        let by_cpu = processes
+            .iter()
+            .take(args.top_processes)
+            .map(|p| ProcessSample::new(p))
+            .collect();
+
+        processes.sort_unstable_by_key(|p| std::cmp::Reverse(p.memory()));
+        let by_memory = processes
+            .iter()
+            .take(args.top_processes)
+            .map(|p| ProcessSample::new(p))
+            .collect();
+
+        TopSample { by_cpu, by_memory }
+    }
+
+    /// Formats the processes of a [`TopProcesses::sample()`] for the table, looking up their users.
+    pub(crate) fn collect(sample: &TopSample, users: &mut UserNames) -> Self {
+        Self {
+            by_cpu: sample
+                .by_cpu
+                .iter()
+                .map(|p| ProcessInfo::new(p, users))
+                .collect(),
+            by_memory: sample
+                .by_memory
+                .iter()
+                .map(|p| ProcessInfo::new(p, users))
+                .collect(),
+        }
+    }
+}
+
+/// Describes data used in the `<theme>/processes.html` [`askama`] templates.
+pub(crate) struct ProcessesData {
+    pub(crate) csp_nonce: String,
+    /// `false` if `--top-processes` is `0`
+    pub(crate) enabled: bool,
+    pub(crate) processes: TopProcesses,
+}
+
+impl ProcessesData {
+    /// `(id, legend, processes)` of every table.
+    pub(crate) fn tables(&self) -> [(&'static str, &'static str, &[ProcessInfo]); 2] {
+        [
+            ("by_cpu", "Top CPU Usage", &self.processes.by_cpu),
+            ("by_memory", "Top Memory Usage", &self.processes.by_memory),
+        ]
+    }
+}
+
+/// Query parameters of `GET /processes`.
+#[derive(Deserialize)]
+pub(crate) struct ProcessesQuery {
+    theme: Option<String>,
+}
+
+/// Handles `GET` requests to `/processes`.
+pub(crate) async fn processes_handler(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    csp_nonce: Option<Extension<CspNonce>>,
+    Query(query): Query<ProcessesQuery>,
+    headers: HeaderMap,
+    state: State<Arc<AppState>>,
+) -> Response {
+    tracing::info!("Connection made to processes from {addr}");
+
+    let processes = state.processes_tx.borrow().clone();
+    let (enabled, default_theme) = {
+        let args = state.args.lock().await;
+        (args.processes.enabled(), args.theme)
+    };
+    let data = ProcessesData {
+        csp_nonce: csp_nonce.map(|Extension(n)| n.0).unwrap_or_default(),
+        enabled,
+        processes,
+    };
+
+    SelectedTheme::select(query.theme.as_deref(), &headers, default_theme).render_processes(&data)
+}
+
+/// Handles `GET` requests to `/api/processes`, returns the latest [`TopProcesses`].
+pub(crate) async fn api_processes_handler(state: State<Arc<AppState>>) -> Json<TopProcesses> {
+    Json(state.processes_tx.borrow().clone())
+}
@@ -0,0 +1,378 @@
+//! RSPI-BIOS push outputs (`InfluxDB`, `StatsD`, Graphite)
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt as _;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::watch;
+
+use crate::snapshot::{MetricValue, SystemSnapshot};
+
+/// Transport used to reach the `StatsD`/Graphite server.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum SocketProtocol {
+    Udp,
+    Tcp,
+}
+
+/// Plaintext format spoken by the `StatsD`/Graphite server.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum PlaintextFormat {
+    /// `prefix.metric:value|g|#tag:value`, tags use the `DogStatsD` extension
+    Statsd,
+    /// `prefix.metric;tag=value value timestamp`, tags need Graphite 1.1+
+    Graphite,
+}
+
+/// CLI parameters for the optional push outputs, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct PushArgs {
+    /// `InfluxDB` write endpoint, enables the `InfluxDB` output when set
+    /// e.g. `http://localhost:8086/api/v2/write?org=home&bucket=rspi` or `http://localhost:8086/write?db=rspi`
+    #[arg(long)]
+    influx_url: Option<reqwest::Url>,
+
+    /// File containing an `InfluxDB` API token, sent as `Authorization: Token <token>`
+    #[arg(long)]
+    influx_token_file: Option<PathBuf>,
+
+    #[arg(long, default_value = "rspi_bios")]
+    influx_measurement: String,
+
+    #[arg(long, value_parser = crate::parse_duration, default_value = "10")]
+    influx_push_interval: Duration,
+
+    /// `StatsD`/Graphite server address e.g. `localhost:8125`, enables the `StatsD`/Graphite output when set
+    #[arg(long)]
+    statsd_address: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = SocketProtocol::Udp)]
+    statsd_protocol: SocketProtocol,
+
+    #[arg(long, value_enum, default_value_t = PlaintextFormat::Statsd)]
+    statsd_format: PlaintextFormat,
+
+    #[arg(long, default_value = "rspi_bios")]
+    statsd_prefix: String,
+
+    #[arg(long, value_parser = crate::parse_duration, default_value = "10")]
+    statsd_push_interval: Duration,
+
+    /// Tag added to every pushed metric, can be repeated e.g. `--push-tag site=attic`.
+    /// A `host` tag is added automatically unless provided.
+    #[arg(long = "push-tag", value_parser = parse_tag)]
+    push_tags: Vec<(String, String)>,
+}
+
+/// Used for parsing `key=value` tags in [`clap`] CLI parameters e.g. `push_tags`
+fn parse_tag(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !value.is_empty() => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected `key=value`, got `{arg}`")),
+    }
+}
+
+/// Wire format a [`SystemSnapshot`] is serialised to before being pushed.
+enum Format {
+    /// See: <https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/>
+    InfluxLineProtocol { measurement: String },
+    Plaintext {
+        format: PlaintextFormat,
+        prefix: String,
+    },
+}
+
+/// Where serialised snapshots are sent to.
+enum Sink {
+    Http {
+        client: reqwest::Client,
+        url: reqwest::Url,
+        token: Option<String>,
+    },
+    Socket {
+        protocol: SocketProtocol,
+        address: String,
+    },
+}
+
+/// A [`Format`] and [`Sink`] pair that pushes the latest snapshot once every `interval`.
+pub(crate) struct PushOutput {
+    name: &'static str,
+    format: Format,
+    sink: Sink,
+    interval: Duration,
+    tags: Vec<(String, String)>,
+}
+
+/// Creates a [`PushOutput`] for every output enabled in the CLI parameters.
+///
+/// Outputs that fail to be created are logged and skipped.
+pub(crate) async fn create_push_outputs(args: &PushArgs) -> Vec<PushOutput> {
+    let mut tags = args.push_tags.clone();
+    if !tags.iter().any(|(key, _)| key == "host") {
+        if let Some(host_name) = sysinfo::System::host_name() {
+            tags.push(("host".to_string(), host_name));
+        }
+    }
+
+    let mut outputs = Vec::new();
+
+    if let Some(url) = &args.influx_url {
+        if let Some(output) = create_influx_output(args, url.clone(), tags.clone()).await {
+            outputs.push(output);
+        }
+    }
+
+    if let Some(address) = &args.statsd_address {
+        outputs.push(PushOutput {
+            name: match args.statsd_format {
+                PlaintextFormat::Statsd => "StatsD",
+                PlaintextFormat::Graphite => "Graphite",
+            },
+            format: Format::Plaintext {
+                format: args.statsd_format,
+                prefix: args.statsd_prefix.clone(),
+            },
+            sink: Sink::Socket {
+                protocol: args.statsd_protocol,
+                address: address.clone(),
+            },
+            interval: args.statsd_push_interval,
+            tags,
+        });
+    }
+
+    outputs
+}
+
+async fn create_influx_output(
+    args: &PushArgs,
+    mut url: reqwest::Url,
+    tags: Vec<(String, String)>,
+) -> Option<PushOutput> {
+    let token = match &args.influx_token_file {
+        Some(path) => match tokio::fs::read_to_string(path).await {
+            Ok(t) => Some(t.trim_end().to_string()),
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to read InfluxDB token from {}, InfluxDB output disabled", path.display());
+                return None;
+            }
+        },
+        None => None,
+    };
+
+    // Timestamps are sent in seconds
+    if !url.query_pairs().any(|(key, _)| key == "precision") {
+        url.query_pairs_mut().append_pair("precision", "s");
+    }
+
+    let client = match reqwest::Client::builder()
+        .timeout(args.influx_push_interval)
+        .build()
+    {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to create InfluxDB HTTP client, InfluxDB output disabled");
+            return None;
+        }
+    };
+
+    Some(PushOutput {
+        name: "InfluxDB",
+        format: Format::InfluxLineProtocol {
+            measurement: args.influx_measurement.clone(),
+        },
+        sink: Sink::Http { client, url, token },
+        interval: args.influx_push_interval,
+        tags,
+    })
+}
+
+impl Format {
+    fn encode(&self, snapshot: &SystemSnapshot, tags: &[(String, String)]) -> String {
+        match self {
+            Self::InfluxLineProtocol { measurement } => encode_influx(measurement, snapshot, tags),
+            Self::Plaintext {
+                format: PlaintextFormat::Statsd,
+                prefix,
+            } => encode_statsd(prefix, snapshot, tags),
+            Self::Plaintext {
+                format: PlaintextFormat::Graphite,
+                prefix,
+            } => encode_graphite(prefix, snapshot, tags),
+        }
+    }
+}
+
+/// Escapes commas, spaces and (optionally) equals signs as required by the `InfluxDB` line protocol.
+fn escape_influx(value: &str, escape_equals: bool) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == ',' || c == ' ' || (escape_equals && c == '=') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Replaces characters `StatsD`/Graphite treat as separators.
+fn sanitize_plaintext(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// `measurement,tag=value uptime=1i,cpu_usage=0.50 timestamp`
+fn encode_influx(
+    measurement: &str,
+    snapshot: &SystemSnapshot,
+    tags: &[(String, String)],
+) -> String {
+    let mut line = escape_influx(measurement, false);
+
+    for (key, value) in tags {
+        let _ = write!(
+            line,
+            ",{}={}",
+            escape_influx(key, true),
+            escape_influx(value, true)
+        );
+    }
+
+    for (i, (metric, value)) in snapshot.metrics().into_iter().enumerate() {
+        let separator = if i == 0 { ' ' } else { ',' };
+        let _ = match value {
+            MetricValue::Integer(v) => write!(line, "{separator}{metric}={v}i"),
+            MetricValue::Float(v) => write!(line, "{separator}{metric}={v}"),
+        };
+    }
+
+    let _ = writeln!(line, " {}", snapshot.timestamp);
+    line
+}
+
+/// `prefix.metric:value|g|#tag:value`, one gauge per line
+fn encode_statsd(prefix: &str, snapshot: &SystemSnapshot, tags: &[(String, String)]) -> String {
+    let tags = tags
+        .iter()
+        .map(|(key, value)| format!("{}:{}", sanitize_plaintext(key), sanitize_plaintext(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let mut lines = String::new();
+    for (metric, value) in snapshot.metrics() {
+        let _ = write!(lines, "{prefix}.{metric}:{value}|g");
+        if !tags.is_empty() {
+            let _ = write!(lines, "|#{tags}");
+        }
+        lines.push('\n');
+    }
+    lines
+}
+
+/// `prefix.metric;tag=value value timestamp`, one metric per line
+fn encode_graphite(prefix: &str, snapshot: &SystemSnapshot, tags: &[(String, String)]) -> String {
+    let mut tag_suffix = String::new();
+    for (key, value) in tags {
+        let _ = write!(
+            tag_suffix,
+            ";{}={}",
+            sanitize_plaintext(key),
+            sanitize_plaintext(value)
+        );
+    }
+
+    let mut lines = String::new();
+    for (metric, value) in snapshot.metrics() {
+        let _ = writeln!(
+            lines,
+            "{prefix}.{metric}{tag_suffix} {value} {}",
+            snapshot.timestamp
+        );
+    }
+    lines
+}
+
+impl Sink {
+    async fn send(&self, payload: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            Self::Http { client, url, token } => {
+                let mut request = client.post(url.clone()).body(payload);
+                if let Some(token) = token {
+                    request = request.header("Authorization", format!("Token {token}"));
+                }
+                request.send().await?.error_for_status()?;
+            }
+            Self::Socket {
+                protocol: SocketProtocol::Udp,
+                address,
+            } => {
+                let addr = tokio::net::lookup_host(address)
+                    .await?
+                    .next()
+                    .ok_or("address resolved to nothing")?;
+                let bind_addr: std::net::SocketAddr = if addr.is_ipv4() {
+                    (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+                } else {
+                    (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+                };
+                let socket = UdpSocket::bind(bind_addr).await?;
+                socket.connect(addr).await?;
+                socket.send(payload.as_bytes()).await?;
+            }
+            Self::Socket {
+                protocol: SocketProtocol::Tcp,
+                address,
+            } => {
+                let mut stream = TcpStream::connect(address).await?;
+                stream.write_all(payload.as_bytes()).await?;
+                stream.shutdown().await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Pushes the latest [`SystemSnapshot`] sent by `send_system_messages()` once every `interval`.
+///
+/// Failed pushes are logged and dropped, the next push sends fresh data anyway.
+pub(crate) async fn push_system_messages(
+    output: PushOutput,
+    snapshot_rx: watch::Receiver<SystemSnapshot>,
+) {
+    tracing::info!(
+        "Pushing system information to {} every {:?}",
+        output.name,
+        output.interval
+    );
+
+    let mut interval = tokio::time::interval(output.interval);
+    loop {
+        interval.tick().await;
+
+        let snapshot = snapshot_rx.borrow().clone();
+        if snapshot.timestamp == 0 {
+            continue;
+        }
+
+        let payload = output.format.encode(&snapshot, &output.tags);
+        if let Err(e) = output.sink.send(payload).await {
+            tracing::warn!(error = %e, "Failed to push system information to {}", output.name);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! RSPI-BIOS health screen rendering
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_4X6, FONT_6X10},
+        MonoFont, MonoTextStyle,
+    },
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+use crate::api::{ApiSnapshot, SystemInfo};
+use crate::theme::{SelectedTheme, Theme};
+use crate::tui::{HealthLines, WIDTH};
+use crate::AppState;
+
+/// Rows of the screen: borders, 2 header lines, divider, 9 spec lines and the status line.
+const ROWS: u32 = 15;
+
+/// Largest font first, the first one fitting the screen is used.
+const FONTS: [&MonoFont<'static>; 3] = [&FONT_10X20, &FONT_6X10, &FONT_4X6];
+
+/// Colours of a theme's health screen.
+pub(crate) struct Palette {
+    background: Rgb888,
+    text: Rgb888,
+    /// First letter of every spec line
+    highlight: Rgb888,
+    border: Rgb888,
+    status: Rgb888,
+}
+
+impl Palette {
+    /// Matches the `#scandisk` colours of `<theme>/index.css`.
+    pub(crate) const fn for_theme(theme: Theme) -> Self {
+        match theme {
+            Theme::Award => Self {
+                background: Rgb888::new(0, 0, 168),
+                text: Rgb888::new(84, 255, 255),
+                highlight: Rgb888::new(255, 255, 84),
+                border: Rgb888::new(84, 255, 255),
+                status: Rgb888::new(255, 255, 255),
+            },
+            Theme::Ami => Self {
+                background: Rgb888::new(192, 192, 192),
+                text: Rgb888::new(0, 0, 168),
+                highlight: Rgb888::new(255, 255, 255),
+                border: Rgb888::new(0, 0, 168),
+                status: Rgb888::new(0, 0, 168),
+            },
+            Theme::Phoenix => Self {
+                background: Rgb888::new(168, 168, 168),
+                text: Rgb888::new(0, 0, 168),
+                highlight: Rgb888::new(255, 255, 255),
+                border: Rgb888::new(0, 168, 168),
+                status: Rgb888::new(0, 0, 168),
+            },
+        }
+    }
+}
+
+/// Converts pixel sizes to coordinates, screens are far smaller than [`i32::MAX`].
+fn coordinate(value: u32) -> i32 {
+    i32::try_from(value).unwrap_or(i32::MAX)
+}
+
+/// Draws the health screen centered on `target` with the largest font that fits, `status` is shown below it.
+pub(crate) fn draw_health_screen<D>(
+    target: &mut D,
+    current: &ApiSnapshot,
+    status: &str,
+    palette: &Palette,
+) where
+    D: DrawTarget<Color = Rgb888> + OriginDimensions,
+{
+    let lines = HealthLines::new(current);
+    let screen = target.size();
+    let columns = u32::try_from(WIDTH).unwrap_or(0) + 4;
+    let font = FONTS
+        .into_iter()
+        .find(|f| {
+            columns * f.character_size.width <= screen.width
+                && ROWS * f.character_size.height <= screen.height
+        })
+        .unwrap_or(&FONT_4X6);
+    let Size {
+        width: char_width,
+        height: char_height,
+    } = font.character_size;
+
+    let box_size = Size::new(columns * char_width, (ROWS - 1) * char_height);
+    let origin = Point::new(
+        coordinate(screen.width.saturating_sub(box_size.width) / 2),
+        coordinate(screen.height.saturating_sub(ROWS * char_height) / 2),
+    );
+    // Top left corner of a character cell
+    let cell = |column: u32, row: u32| {
+        origin
+            + Point::new(
+                coordinate(column * char_width),
+                coordinate(row * char_height),
+            )
+    };
+
+    let _ = target.clear(palette.background);
+
+    // Double border with a single line under the header, like the box drawing characters in the terminal
+    let border = PrimitiveStyle::with_stroke(palette.border, 2);
+    let half_cell = Point::new(coordinate(char_width / 2), coordinate(char_height / 2));
+    for inset in [0, 4] {
+        let _ = Rectangle::new(
+            origin + half_cell + Point::new(inset, inset),
+            box_size - Size::new(char_width, char_height) - Size::new(2, 2) * inset.unsigned_abs(),
+        )
+        .into_styled(border)
+        .draw(target);
+    }
+    let divider_y = cell(0, 3).y + half_cell.y;
+    let _ = Line::new(
+        Point::new(origin.x + half_cell.x, divider_y),
+        Point::new(
+            origin.x + coordinate(box_size.width) - half_cell.x,
+            divider_y,
+        ),
+    )
+    .into_styled(PrimitiveStyle::with_stroke(palette.border, 1))
+    .draw(target);
+
+    let text = MonoTextStyle::new(font, palette.text);
+    let highlight = MonoTextStyle::new(font, palette.highlight);
+    for (row, line) in (1..).zip(&lines.header) {
+        let _ = Text::with_baseline(line, cell(2, row), text, Baseline::Top).draw(target);
+    }
+    for (row, line) in (4..).zip(&lines.specs) {
+        let (first, rest) = line.split_at(line.chars().next().map_or(0, char::len_utf8));
+        let _ = Text::with_baseline(first, cell(2, row), highlight, Baseline::Top).draw(target);
+        let _ = Text::with_baseline(rest, cell(3, row), text, Baseline::Top).draw(target);
+    }
+    let _ = Text::with_baseline(
+        status,
+        cell(0, ROWS - 1),
+        MonoTextStyle::new(font, palette.status),
+        Baseline::Top,
+    )
+    .draw(target);
+}
+
+/// Status line shown below the health screen.
+pub(crate) fn status_line(system: &SystemInfo) -> String {
+    format!(
+        "rspi-bios {} - {}",
+        system.version,
+        OffsetDateTime::now_utc()
+            .format(time::macros::format_description!(
+                "[year]-[month]-[day] [hour]:[minute]:[second] UTC"
+            ))
+            .unwrap_or_default()
+    )
+}
+
+/// RGB image the health screen is drawn on for `/snapshot.png`.
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; usize::try_from(width * height * 3).unwrap_or(0)],
+        }
+    }
+
+    fn to_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(png)
+    }
+}
+
+impl OriginDimensions for Canvas {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl DrawTarget for Canvas {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let (Ok(x), Ok(y)) = (u32::try_from(point.x), u32::try_from(point.y)) else {
+                continue;
+            };
+            if x >= self.width || y >= self.height {
+                continue;
+            }
+
+            let offset = usize::try_from((y * self.width + x) * 3).unwrap_or(usize::MAX);
+            if let Some(pixel) = self.pixels.get_mut(offset..offset.saturating_add(3)) {
+                pixel.copy_from_slice(&[color.r(), color.g(), color.b()]);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Query parameters of `GET /snapshot.png`.
+#[derive(Deserialize)]
+pub(crate) struct SnapshotQuery {
+    #[serde(default = "default_width")]
+    width: u32,
+    #[serde(default = "default_height")]
+    height: u32,
+    /// Defaults to the theme used by the dashboard, see [`SelectedTheme::select`]
+    theme: Option<String>,
+}
+
+const fn default_width() -> u32 {
+    800
+}
+
+const fn default_height() -> u32 {
+    480
+}
+
+/// Handles `GET` requests to `/snapshot.png`, renders the health screen for e-paper displays and link previews.
+///
+/// Returns `400 Bad Request` if the size is outside of 320x120 to 1920x1080.
+pub(crate) async fn snapshot_png_handler(
+    Query(query): Query<SnapshotQuery>,
+    headers: HeaderMap,
+    state: State<Arc<AppState>>,
+) -> Response {
+    if !(320..=1920).contains(&query.width) || !(120..=1080).contains(&query.height) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Size must be between 320x120 and 1920x1080",
+        )
+            .into_response();
+    }
+
+    let default_theme = state.args.lock().await.theme;
+    let theme = SelectedTheme::select(query.theme.as_deref(), &headers, default_theme).theme;
+    let current = ApiSnapshot {
+        system: SystemInfo::from_state(&state).await,
+        snapshot: state.snapshot_tx.borrow().clone(),
+    };
+
+    // Drawing and encoding up to 1920x1080 would hold up other requests on this worker
+    let encoded = tokio::task::spawn_blocking(move || {
+        let mut canvas = Canvas::new(query.width, query.height);
+        draw_health_screen(
+            &mut canvas,
+            &current,
+            &status_line(&current.system),
+            &Palette::for_theme(theme),
+        );
+        canvas.to_png().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()));
+
+    match encoded {
+        Ok(png) => (
+            [
+                (header::CONTENT_TYPE, "image/png"),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            png,
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to encode snapshot.png");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! RSPI-BIOS security headers
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use base64::Engine as _;
+
+use crate::listeners::TlsConnection;
+
+/// Value of the `Referrer-Policy` header.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum ReferrerPolicy {
+    NoReferrer,
+    NoReferrerWhenDowngrade,
+    Origin,
+    OriginWhenCrossOrigin,
+    SameOrigin,
+    StrictOrigin,
+    StrictOriginWhenCrossOrigin,
+}
+
+impl ReferrerPolicy {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::NoReferrer => "no-referrer",
+            Self::NoReferrerWhenDowngrade => "no-referrer-when-downgrade",
+            Self::Origin => "origin",
+            Self::OriginWhenCrossOrigin => "origin-when-cross-origin",
+            Self::SameOrigin => "same-origin",
+            Self::StrictOrigin => "strict-origin",
+            Self::StrictOriginWhenCrossOrigin => "strict-origin-when-cross-origin",
+        }
+    }
+}
+
+/// CLI parameters for the dashboard security headers, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct SecurityArgs {
+    /// Don't send `Content-Security-Policy`, `Strict-Transport-Security`, etc. e.g. if a reverse proxy adds them
+    #[arg(long)]
+    disable_security_headers: bool,
+
+    /// `Strict-Transport-Security` max-age, only sent over `HTTPS`, `0` tells browsers to forget it
+    #[arg(long, value_parser = crate::parse_duration, default_value = "31536000")]
+    hsts_max_age: Duration,
+
+    #[arg(long)]
+    hsts_include_subdomains: bool,
+
+    /// CSP `frame-ancestors` source allowed to embed the dashboard e.g. `'self'` or `https://home.example.com`,
+    /// can be repeated
+    #[arg(long = "frame-ancestor", value_parser = parse_csp_source, default_value = "'none'")]
+    frame_ancestors: Vec<String>,
+
+    #[arg(long, value_enum, default_value_t = ReferrerPolicy::NoReferrer)]
+    referrer_policy: ReferrerPolicy,
+}
+
+/// Rejects values that could inject extra CSP directives or aren't valid in a header.
+fn parse_csp_source(s: &str) -> Result<String, String> {
+    if s.is_empty()
+        || s.chars()
+            .any(|c| c == ';' || c == ',' || !c.is_ascii_graphic())
+    {
+        return Err(format!("invalid CSP source `{s}`"));
+    }
+    Ok(s.to_string())
+}
+
+/// Random value allowing the dashboard's `<script>` elements under the `Content-Security-Policy`.
+///
+/// Inserted into request extensions by [`security_headers_middleware`], a new one is generated for every response.
+#[derive(Clone, Debug)]
+pub(crate) struct CspNonce(pub(crate) String);
+
+/// Headers added to every dashboard response, built once from [`SecurityArgs`].
+pub(crate) struct SecurityHeaders {
+    hsts: HeaderValue,
+    frame_ancestors: String,
+    x_frame_options: Option<HeaderValue>,
+    referrer_policy: HeaderValue,
+}
+
+impl SecurityHeaders {
+    /// Returns [`None`] if security headers are disabled.
+    pub(crate) fn new(args: &SecurityArgs) -> Option<Arc<Self>> {
+        if args.disable_security_headers {
+            return None;
+        }
+
+        let mut hsts = format!("max-age={}", args.hsts_max_age.as_secs());
+        if args.hsts_include_subdomains {
+            hsts.push_str("; includeSubDomains");
+        }
+
+        // Only for older browsers that don't support `frame-ancestors`
+        let x_frame_options = match args.frame_ancestors.as_slice() {
+            [source] if source == "'none'" => Some(HeaderValue::from_static("DENY")),
+            [source] if source == "'self'" => Some(HeaderValue::from_static("SAMEORIGIN")),
+            _ => None,
+        };
+
+        Some(Arc::new(Self {
+            hsts: HeaderValue::from_str(&hsts).ok()?,
+            frame_ancestors: args.frame_ancestors.join(" "),
+            x_frame_options,
+            referrer_policy: HeaderValue::from_static(args.referrer_policy.as_str()),
+        }))
+    }
+
+    /// Scripts are only allowed with the nonce, styles and images are allowed from the CDNs used by `index.html`.
+    ///
+    /// Inline `style` attributes are still allowed since the template relies on them, they can't run code.
+    fn content_security_policy(&self, nonce: &str) -> String {
+        format!(
+            "default-src 'self'; \
+             script-src 'nonce-{nonce}'; \
+             style-src 'self' https://cdn.jsdelivr.net; \
+             style-src-attr 'unsafe-inline'; \
+             img-src 'self' data: https://cdn.jsdelivr.net https://upload.wikimedia.org; \
+             font-src 'self' https://cdn.jsdelivr.net; \
+             connect-src 'self'; \
+             object-src 'none'; \
+             base-uri 'none'; \
+             form-action 'self'; \
+             frame-ancestors {}",
+            self.frame_ancestors
+        )
+    }
+}
+
+/// Generates a base64 encoded 128-bit [`CspNonce`].
+fn generate_nonce() -> Option<CspNonce> {
+    let mut bytes = [0_u8; 16];
+    if let Err(e) = getrandom::getrandom(&mut bytes) {
+        tracing::error!(error = %e, "Failed to generate CSP nonce");
+        return None;
+    }
+    Some(CspNonce(
+        base64::engine::general_purpose::STANDARD.encode(bytes),
+    ))
+}
+
+/// Adds [`SecurityHeaders`] to responses, `Strict-Transport-Security` is only added to responses sent over TLS.
+///
+/// See: <https://developer.mozilla.org/en-US/docs/Web/HTTP/CSP>
+pub(crate) async fn security_headers_middleware(
+    State(security_headers): State<Arc<SecurityHeaders>>,
+    tls: Option<Extension<TlsConnection>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(nonce) = generate_nonce() else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let csp = HeaderValue::from_str(&security_headers.content_security_policy(&nonce.0));
+    request.extensions_mut().insert(nonce);
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+
+    match csp {
+        Ok(csp) => {
+            headers.insert(header::CONTENT_SECURITY_POLICY, csp);
+        }
+        Err(e) => tracing::error!(error = %e, "Failed to create Content-Security-Policy header"),
+    }
+    if tls.is_some() {
+        headers.insert(
+            header::STRICT_TRANSPORT_SECURITY,
+            security_headers.hsts.clone(),
+        );
+    }
+    if let Some(x_frame_options) = &security_headers.x_frame_options {
+        headers.insert(header::X_FRAME_OPTIONS, x_frame_options.clone());
+    }
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(
+        header::REFERRER_POLICY,
+        security_headers.referrer_policy.clone(),
+    );
+
+    response
+}
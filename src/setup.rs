@@ -0,0 +1,410 @@
+//! RSPI-BIOS SETUP screen
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{ConnectInfo, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Extension, Form, Router,
+};
+use clap::ValueEnum;
+use serde::Deserialize;
+use sysinfo::{Components, System};
+use time::OffsetDateTime;
+
+use crate::admin::token_matches;
+use crate::api;
+use crate::boot::BootMode;
+use crate::config::ConfigFile;
+use crate::listeners::TlsConnection;
+use crate::power::{Power, PowerAction};
+use crate::security::CspNonce;
+use crate::theme::{SelectedTheme, Theme};
+use crate::units::{self, UnitStatus};
+use crate::AppState;
+
+/// Describes data used in the `<theme>/setup.html` [`askama`] templates.
+#[allow(clippy::struct_excessive_bools)]
+pub(crate) struct SetupData {
+    pub(crate) csp_nonce: String,
+    /// Settings can only be changed after logging in with the admin token
+    pub(crate) authenticated: bool,
+    pub(crate) admin_enabled: bool,
+    pub(crate) invalid_password: bool,
+    /// Sent back by the power action forms, empty if not authenticated
+    pub(crate) csrf_token: String,
+    /// `false` unless `--enable-power-actions` is set
+    pub(crate) power_enabled: bool,
+    pub(crate) power_dry_run: bool,
+    /// Units that can be restarted
+    pub(crate) power_units: Vec<String>,
+    /// Label of the power action that was just requested
+    pub(crate) power_requested: Option<&'static str>,
+    /// `false` unless `--status-unit` or `--status-failed-units` is set
+    pub(crate) units_enabled: bool,
+    /// Priority label and state of every unit
+    pub(crate) units: Vec<(String, UnitStatus)>,
+    /// `false` unless `--container-socket` is set
+    pub(crate) containers_enabled: bool,
+    /// `false` if `--top-processes` is `0`
+    pub(crate) processes_enabled: bool,
+    /// `false` if changes only last until the next restart
+    pub(crate) persistent: bool,
+    pub(crate) date: String,
+    pub(crate) time: String,
+    pub(crate) model_name: String,
+    pub(crate) host_name: String,
+    pub(crate) kernel_version: String,
+    pub(crate) os_version: String,
+    pub(crate) cpu_brand: String,
+    pub(crate) cpu_arch: String,
+    pub(crate) cpu_count: usize,
+    pub(crate) cpu_speed: u64,
+    pub(crate) total_memory: u64,
+    pub(crate) used_memory: u64,
+    pub(crate) primary_disk_size: u64,
+    pub(crate) version: String,
+    pub(crate) cpu_usage: String,
+    pub(crate) temperatures: Vec<(String, String)>,
+    pub(crate) load_average: String,
+    pub(crate) uptime: String,
+    pub(crate) process_count: usize,
+    pub(crate) system_refresh_interval: u64,
+    pub(crate) sse_keep_alive_interval: u64,
+    /// Value, label and whether it is the default theme
+    pub(crate) theme_options: Vec<(&'static str, &'static str, bool)>,
+    /// Value, label and whether it is the default boot mode
+    pub(crate) boot_mode_options: Vec<(&'static str, &'static str, bool)>,
+}
+
+/// Query parameters of `GET /setup`.
+#[derive(Deserialize)]
+struct SetupQuery {
+    error: Option<String>,
+    theme: Option<String>,
+    /// Set after a power action was requested
+    power: Option<PowerAction>,
+}
+
+/// Lists every variant of a setting as value, label and whether it is `current`, for `<select>` elements.
+fn options<T: ValueEnum + PartialEq + Copy>(
+    current: T,
+    value: fn(T) -> &'static str,
+    label: fn(T) -> &'static str,
+) -> Vec<(&'static str, &'static str, bool)> {
+    T::value_variants()
+        .iter()
+        .map(|&v| (value(v), label(v), v == current))
+        .collect()
+}
+
+/// Handles `GET` requests to `/setup`.
+#[allow(clippy::too_many_lines)]
+async fn setup_handler(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    csp_nonce: Option<Extension<CspNonce>>,
+    Query(query): Query<SetupQuery>,
+    headers: HeaderMap,
+    state: State<Arc<AppState>>,
+) -> Response {
+    tracing::info!("Connection made to SETUP from {addr}");
+
+    let now = OffsetDateTime::now_utc();
+    let snapshot = state.snapshot_tx.borrow().clone();
+
+    let (cpu_brand, cpu_count, cpu_speed) = api::cpu(&*state.system.lock().await);
+    let primary_disk_size = state
+        .disks
+        .lock()
+        .await
+        .first()
+        .map_or(0, sysinfo::Disk::total_space);
+
+    let temperatures = Components::new_with_refreshed_list()
+        .iter()
+        .map(|c| {
+            (
+                c.label().to_string(),
+                format!("{:.1}\u{b0}C", c.temperature()),
+            )
+        })
+        .collect();
+    let load_average = System::load_average();
+    let uptime = Duration::from_secs(snapshot.uptime);
+
+    let (
+        theme,
+        system_refresh_interval,
+        sse_keep_alive_interval,
+        theme_options,
+        boot_mode_options,
+        units_enabled,
+        containers_enabled,
+        processes_enabled,
+    ) = {
+        let args = state.args.lock().await;
+        (
+            SelectedTheme::select(query.theme.as_deref(), &headers, args.theme),
+            args.system_refresh_interval.as_secs(),
+            args.sse_keep_alive_interval.as_secs(),
+            options(args.theme, Theme::as_str, Theme::label),
+            options(args.boot_mode, BootMode::as_str, BootMode::label),
+            args.units.enabled(),
+            args.containers.enabled(),
+            args.processes.enabled(),
+        )
+    };
+
+    let csrf_token = state.sessions.csrf_token(&headers);
+    let units = units::prioritized(&state.units_tx.borrow());
+    let data = SetupData {
+        csp_nonce: csp_nonce.map(|Extension(n)| n.0).unwrap_or_default(),
+        authenticated: csrf_token.is_some(),
+        admin_enabled: state.admin_token.is_some(),
+        invalid_password: query.error.as_deref() == Some("password"),
+        csrf_token: csrf_token.unwrap_or_default(),
+        power_enabled: state.power.is_some(),
+        power_dry_run: state.power.as_ref().is_some_and(Power::dry_run),
+        power_units: state
+            .power
+            .as_ref()
+            .map(|p| p.units().to_vec())
+            .unwrap_or_default(),
+        power_requested: query.power.map(PowerAction::label),
+        units_enabled,
+        units,
+        containers_enabled,
+        processes_enabled,
+        persistent: state.config_file.is_some(),
+        date: now
+            .format(time::macros::format_description!(
+                "[weekday repr:short] [month repr:short] [day] [year]"
+            ))
+            .unwrap_or_default(),
+        time: now
+            .format(time::macros::format_description!(
+                "[hour]:[minute]:[second] UTC"
+            ))
+            .unwrap_or_default(),
+        model_name: state.model_name.clone(),
+        host_name: System::host_name().unwrap_or_default(),
+        kernel_version: state.kernel_version.lock().await.to_string(),
+        os_version: state.os_version.lock().await.to_string(),
+        cpu_brand,
+        cpu_arch: state.cpu_arch.lock().await.to_string(),
+        cpu_count,
+        cpu_speed,
+        total_memory: snapshot.total_memory / 1_048_576,
+        used_memory: snapshot.used_memory / 1_048_576,
+        primary_disk_size: primary_disk_size / 1_000_000_000,
+        version: env!("RSPI_BIOS_VERSION").to_string(),
+        cpu_usage: format!("{:.1}%", snapshot.cpu_usage),
+        temperatures,
+        load_average: format!(
+            "{:.2} {:.2} {:.2}",
+            load_average.one, load_average.five, load_average.fifteen
+        ),
+        uptime: format!(
+            "{}h {:02}m",
+            uptime.as_secs() / 3_600,
+            uptime.as_secs() / 60 % 60
+        ),
+        process_count: snapshot.process_count,
+        system_refresh_interval,
+        sse_keep_alive_interval,
+        theme_options,
+        boot_mode_options,
+    };
+
+    theme.render_setup(&data)
+}
+
+/// Body of `POST /setup/login` requests.
+#[derive(Deserialize)]
+struct LoginForm {
+    token: String,
+}
+
+/// Handles `POST` requests to `/setup/login`, starts a session if the admin token is correct.
+async fn login_handler(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    tls: Option<Extension<TlsConnection>>,
+    state: State<Arc<AppState>>,
+    Form(form): Form<LoginForm>,
+) -> Response {
+    let valid = state
+        .admin_token
+        .as_deref()
+        .is_some_and(|t| token_matches(t, &form.token));
+    if !valid {
+        tracing::warn!("Rejected SETUP login from {addr}");
+        return Redirect::to("../setup?error=password#supervisor").into_response();
+    }
+
+    let Some(cookie) = state.sessions.create(tls.is_some()) else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    tracing::info!("SETUP login from {addr}");
+    (
+        [(header::SET_COOKIE, cookie)],
+        Redirect::to("../setup#supervisor"),
+    )
+        .into_response()
+}
+
+/// Handles `POST` requests to `/setup/logout`.
+async fn logout_handler(headers: HeaderMap, state: State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::SET_COOKIE, state.sessions.remove(&headers))],
+        Redirect::to("../setup"),
+    )
+}
+
+/// Body of `POST /setup/save` requests, values are in seconds.
+#[derive(Deserialize)]
+struct SettingsForm {
+    system_refresh_interval: u64,
+    sse_keep_alive_interval: u64,
+    theme: Theme,
+    boot_mode: BootMode,
+    /// See [`crate::admin::Sessions::csrf_matches`]
+    #[serde(default)]
+    csrf_token: String,
+}
+
+/// Body of `POST /setup/defaults` requests.
+#[derive(Deserialize)]
+struct DefaultsForm {
+    #[serde(default)]
+    csrf_token: String,
+}
+
+/// Returns `403 Forbidden` if `csrf_token` doesn't match the session, like the power actions.
+fn reject_csrf(
+    state: &AppState,
+    headers: &HeaderMap,
+    csrf_token: &str,
+    addr: SocketAddr,
+) -> Option<Response> {
+    if state.sessions.csrf_matches(headers, csrf_token) {
+        return None;
+    }
+    tracing::warn!("Rejected SETUP change with an invalid CSRF token from {addr}");
+    Some((StatusCode::FORBIDDEN, "Invalid CSRF token, reload SETUP").into_response())
+}
+
+/// Applies `config` and writes it to `--config-file` if set.
+///
+/// Returns `false` if writing failed, the settings are still applied until the next restart.
+async fn store(state: &AppState, config: ConfigFile) -> bool {
+    config.apply(&mut *state.args.lock().await);
+
+    let mut current = state.config.lock().await;
+    *current = config;
+    let Some(config_file) = &state.config_file else {
+        return true;
+    };
+    match current.save(config_file).await {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to write {}", config_file.display());
+            false
+        }
+    }
+}
+
+/// Handles `POST` requests to `/setup/save` ("Save & Exit Setup"), goes back to the dashboard.
+///
+/// Returns `400 Bad Request` if a setting is invalid and `403 Forbidden` if the CSRF token doesn't match the session.
+async fn save_handler(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    state: State<Arc<AppState>>,
+    Form(form): Form<SettingsForm>,
+) -> Response {
+    if let Some(response) = reject_csrf(&state, &headers, &form.csrf_token, addr) {
+        return response;
+    }
+
+    let config = ConfigFile {
+        system_refresh_interval: Some(form.system_refresh_interval),
+        sse_keep_alive_interval: Some(form.sse_keep_alive_interval),
+        theme: Some(form.theme),
+        boot_mode: Some(form.boot_mode),
+    };
+    if let Err(e) = config.validate() {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    if !store(&state, config).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Settings applied but couldn't be saved to the config file",
+        )
+            .into_response();
+    }
+    tracing::info!("Settings saved in SETUP by {addr}");
+    Redirect::to("../").into_response()
+}
+
+/// Handles `POST` requests to `/setup/defaults` ("Load Defaults"), reverts every setting to the CLI parameters.
+///
+/// Returns `403 Forbidden` if the CSRF token doesn't match the session.
+async fn defaults_handler(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    state: State<Arc<AppState>>,
+    Form(form): Form<DefaultsForm>,
+) -> Response {
+    if let Some(response) = reject_csrf(&state, &headers, &form.csrf_token, addr) {
+        return response;
+    }
+
+    state.setup_defaults.apply(&mut *state.args.lock().await);
+    if !store(&state, ConfigFile::default()).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Defaults loaded but couldn't be saved to the config file",
+        )
+            .into_response();
+    }
+    tracing::info!("Defaults loaded in SETUP by {addr}");
+    Redirect::to("../setup").into_response()
+}
+
+/// Rejects requests without a valid session cookie.
+pub(crate) async fn require_session(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    state: State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.sessions.is_valid(request.headers()) {
+        tracing::warn!(uri = %request.uri(), "Rejected unauthenticated SETUP request from {addr}");
+        return (
+            StatusCode::UNAUTHORIZED,
+            "Enter the supervisor password in SETUP first",
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Creates the routes under `/setup`, changing settings requires logging in with the admin token.
+pub(crate) fn setup_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/setup/save", post(save_handler))
+        .route("/setup/defaults", post(defaults_handler))
+        .route_layer(middleware::from_fn_with_state(state, require_session))
+        .route("/setup", get(setup_handler))
+        .route("/setup/login", post(login_handler))
+        .route("/setup/logout", post(logout_handler))
+}
@@ -0,0 +1,127 @@
+//! RSPI-BIOS system snapshot
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sysinfo::{Networks, ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+
+/// Dynamic system information collected by `send_system_messages()` once every `system_refresh_interval`.
+///
+/// Every output (SSE, MQTT, etc.) reads the same snapshot so they all report the same numbers.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct SystemSnapshot {
+    /// Seconds since the Unix epoch at which the snapshot was taken.
+    pub(crate) timestamp: u64,
+    /// Seconds since boot.
+    pub(crate) uptime: u64,
+    pub(crate) process_count: usize,
+    /// Total bytes received by all network interfaces.
+    pub(crate) rx: u64,
+    /// Total bytes transmitted by all network interfaces.
+    pub(crate) tx: u64,
+    /// Global CPU usage in percent.
+    pub(crate) cpu_usage: f32,
+    /// Used memory in bytes.
+    pub(crate) used_memory: u64,
+    /// Total memory in bytes.
+    pub(crate) total_memory: u64,
+}
+
+/// Value of a single metric taken from a [`SystemSnapshot`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum MetricValue {
+    Integer(u64),
+    Float(f32),
+}
+
+impl fmt::Display for MetricValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(i) => write!(f, "{i}"),
+            Self::Float(v) => write!(f, "{v:.2}"),
+        }
+    }
+}
+
+impl SystemSnapshot {
+    /// Returns the current time as seconds since the Unix epoch, `0` if the clock is before it.
+    pub(crate) fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+
+    /// Refreshes `system` and `networks` and takes a snapshot of them.
+    ///
+    /// `top_processes` also reads the command line and user of new processes for `TopProcesses`.
+    pub(crate) fn collect(
+        system: &mut System,
+        networks: &mut Networks,
+        top_processes: bool,
+    ) -> Self {
+        // Same as `refresh_processes()`
+        let refresh_kind = ProcessRefreshKind::new()
+            .with_memory()
+            .with_cpu()
+            .with_disk_usage()
+            .with_exe(UpdateKind::OnlyIfNotSet);
+        let refresh_kind = if top_processes {
+            // Only read once per process
+            refresh_kind
+                .with_cmd(UpdateKind::OnlyIfNotSet)
+                .with_user(UpdateKind::OnlyIfNotSet)
+        } else {
+            refresh_kind
+        };
+        system.refresh_processes_specifics(ProcessesToUpdate::All, refresh_kind);
+        system.refresh_cpu_usage();
+        system.refresh_memory();
+        networks.refresh();
+
+        let (rx, tx) = networks
+            .iter()
+            .fold((0, 0), |(rx, tx), (_interface_name, data)| {
+                (rx + data.total_received(), tx + data.total_transmitted())
+            });
+
+        Self {
+            timestamp: Self::now(),
+            uptime: System::uptime(),
+            process_count: system.processes().len(),
+            rx,
+            tx,
+            cpu_usage: system.global_cpu_usage(),
+            used_memory: system.used_memory(),
+            total_memory: system.total_memory(),
+        }
+    }
+
+    /// Formats the snapshot the way the `index.html` SSE handler expects it i.e. `rx, tx, process_count, uptime`.
+    pub(crate) fn to_sse_data(&self) -> String {
+        format!(
+            "{:?}, {:?}, {:?}, {:?}",
+            self.rx, self.tx, self.process_count, self.uptime
+        )
+    }
+
+    /// Returns every metric in the snapshot as `(name, value)` pairs.
+    ///
+    /// Names are stable and used as-is in topic names, field names, etc.
+    pub(crate) fn metrics(&self) -> [(&'static str, MetricValue); 7] {
+        [
+            ("uptime", MetricValue::Integer(self.uptime)),
+            (
+                "process_count",
+                MetricValue::Integer(u64::try_from(self.process_count).unwrap_or(u64::MAX)),
+            ),
+            ("rx", MetricValue::Integer(self.rx)),
+            ("tx", MetricValue::Integer(self.tx)),
+            ("cpu_usage", MetricValue::Float(self.cpu_usage)),
+            ("used_memory", MetricValue::Integer(self.used_memory)),
+            ("total_memory", MetricValue::Integer(self.total_memory)),
+        ]
+    }
+}
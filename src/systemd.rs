@@ -0,0 +1,109 @@
+//! RSPI-BIOS systemd integration
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::time::Duration;
+
+use listenfd::ListenFd;
+
+/// Names given to sockets in the order they are received when systemd doesn't name them, see `debian/rspi-bios.socket`.
+const DEFAULT_SOCKET_NAMES: [&str; 2] = ["https", "http"];
+
+/// Listening sockets passed in by systemd socket activation (`LISTEN_FDS`).
+///
+/// Lets the binary serve on low ports without `CAP_NET_BIND_SERVICE`.
+///
+/// See: <https://www.freedesktop.org/software/systemd/man/latest/sd_listen_fds.html>
+pub(crate) struct ActivatedSockets {
+    listen_fd: ListenFd,
+    names: Vec<String>,
+}
+
+impl ActivatedSockets {
+    /// Sockets are named using `FileDescriptorName=`, if none of them are (systemd defaults to the socket unit name)
+    /// then [`DEFAULT_SOCKET_NAMES`] are used in order.
+    pub(crate) fn from_env() -> Self {
+        let listen_fd = ListenFd::from_env();
+        if listen_fd.len() > 0 {
+            tracing::info!("Received {} socket(s) from systemd", listen_fd.len());
+        }
+
+        let mut names: Vec<String> = std::env::var("LISTEN_FDNAMES")
+            .map(|n| n.split(':').map(str::to_string).collect())
+            .unwrap_or_default();
+
+        if names
+            .iter()
+            .all(|n| n == "unknown" || n.ends_with(".socket"))
+        {
+            names = DEFAULT_SOCKET_NAMES
+                .iter()
+                .take(listen_fd.len())
+                .map(ToString::to_string)
+                .collect();
+        }
+
+        Self { listen_fd, names }
+    }
+
+    /// Returns `true` if a socket named `name` was received and hasn't been taken yet.
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.names.iter().any(|n| n == name)
+    }
+
+    /// Takes the TCP listener named `name`.
+    pub(crate) fn take_tcp_listener(&mut self, name: &str) -> Option<std::net::TcpListener> {
+        let index = self.names.iter().position(|n| n == name)?;
+        // Can only be taken once
+        self.names.get_mut(index)?.clear();
+
+        match self.listen_fd.take_tcp_listener(index) {
+            Ok(l) => l,
+            Err(e) => {
+                tracing::warn!(error = %e, "Socket `{name}` received from systemd is not a TCP listener");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn notify(state: &[sd_notify::NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        tracing::debug!(error = %e, "Failed to notify systemd");
+    }
+}
+
+/// Tells systemd startup is finished when using `Type=notify`, no-op when not started by systemd.
+pub(crate) fn notify_ready(status: &str) {
+    #[cfg(unix)]
+    notify(&[
+        sd_notify::NotifyState::Ready,
+        sd_notify::NotifyState::Status(status),
+    ]);
+    #[cfg(not(unix))]
+    let _ = status;
+}
+
+/// Tells systemd shutdown has started.
+pub(crate) fn notify_stopping() {
+    #[cfg(unix)]
+    notify(&[sd_notify::NotifyState::Stopping]);
+}
+
+/// Pets the systemd watchdog (`WatchdogSec=`), no-op if it isn't enabled.
+pub(crate) fn notify_watchdog() {
+    #[cfg(unix)]
+    notify(&[sd_notify::NotifyState::Watchdog]);
+}
+
+/// Returns the systemd watchdog timeout if it is enabled for this process.
+pub(crate) fn watchdog_timeout() -> Option<Duration> {
+    #[cfg(unix)]
+    {
+        let mut usec = 0;
+        sd_notify::watchdog_enabled(false, &mut usec).then(|| Duration::from_micros(usec))
+    }
+    #[cfg(not(unix))]
+    None
+}
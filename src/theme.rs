@@ -0,0 +1,190 @@
+//! RSPI-BIOS themes
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use askama::Template;
+use axum::{
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use clap::ValueEnum as _;
+use serde::{Deserialize, Serialize};
+
+use crate::admin::cookie;
+use crate::containers::ContainersData;
+use crate::hub::HubData;
+use crate::processes::ProcessesData;
+use crate::setup::SetupData;
+use crate::{HtmlTemplate, IndexData};
+
+/// Cookie remembering the theme picked with `?theme=`.
+pub(crate) const THEME_COOKIE: &str = "rspi_bios_theme";
+
+/// BIOS look, every theme has a `templates/<theme>/` directory with the same pages rendered from the same data.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Theme {
+    /// Award Software, blue setup screen
+    #[default]
+    Award,
+    /// American Megatrends, blue-grey setup screen
+    Ami,
+    /// Phoenix Technologies, grey setup screen with a blue title bar
+    Phoenix,
+}
+
+impl Theme {
+    pub(crate) const fn as_str(self) -> &'static str {
+        match self {
+            Self::Award => "award",
+            Self::Ami => "ami",
+            Self::Phoenix => "phoenix",
+        }
+    }
+
+    pub(crate) const fn label(self) -> &'static str {
+        match self {
+            Self::Award => "Award",
+            Self::Ami => "AMI",
+            Self::Phoenix => "Phoenix",
+        }
+    }
+}
+
+/// Declares a [`Template`] per theme wrapping the same data, fields are reached through [`std::ops::Deref`].
+macro_rules! themed_templates {
+    ($data:ty { $($name:ident => $path:tt),+ $(,)? }) => {
+        $(
+            #[derive(Template)]
+            #[template(path = $path)]
+            struct $name<'a>(&'a $data);
+
+            impl std::ops::Deref for $name<'_> {
+                type Target = $data;
+
+                fn deref(&self) -> &Self::Target {
+                    self.0
+                }
+            }
+        )+
+    };
+}
+
+themed_templates!(IndexData {
+    AwardIndex => "award/index.html",
+    AmiIndex => "ami/index.html",
+    PhoenixIndex => "phoenix/index.html",
+});
+
+themed_templates!(SetupData {
+    AwardSetup => "award/setup.html",
+    AmiSetup => "ami/setup.html",
+    PhoenixSetup => "phoenix/setup.html",
+});
+
+themed_templates!(HubData {
+    AwardHub => "award/hub.html",
+    AmiHub => "ami/hub.html",
+    PhoenixHub => "phoenix/hub.html",
+});
+
+themed_templates!(ContainersData {
+    AwardContainers => "award/containers.html",
+    AmiContainers => "ami/containers.html",
+    PhoenixContainers => "phoenix/containers.html",
+});
+
+themed_templates!(ProcessesData {
+    AwardProcesses => "award/processes.html",
+    AmiProcesses => "ami/processes.html",
+    PhoenixProcesses => "phoenix/processes.html",
+});
+
+/// Theme used for a request.
+pub(crate) struct SelectedTheme {
+    pub(crate) theme: Theme,
+    /// Set if the theme was picked with `?theme=` so it sticks for the following pages
+    set_cookie: Option<HeaderValue>,
+}
+
+impl SelectedTheme {
+    /// Picks `?theme=` if valid, then the [`THEME_COOKIE`], then `default` (`--config-file` or `--theme`).
+    pub(crate) fn select(query: Option<&str>, headers: &HeaderMap, default: Theme) -> Self {
+        let parse = |s: &str| Theme::from_str(s, true).ok();
+
+        if let Some(theme) = query.and_then(parse) {
+            let set_cookie = HeaderValue::from_str(&format!(
+                "{THEME_COOKIE}={}; Max-Age=31536000; Path=/; SameSite=Lax",
+                theme.as_str()
+            ))
+            .ok();
+            return Self { theme, set_cookie };
+        }
+
+        Self {
+            theme: cookie(headers, THEME_COOKIE)
+                .and_then(parse)
+                .unwrap_or(default),
+            set_cookie: None,
+        }
+    }
+
+    fn finish(&self, mut response: Response) -> Response {
+        if let Some(set_cookie) = &self.set_cookie {
+            response
+                .headers_mut()
+                .append(header::SET_COOKIE, set_cookie.clone());
+        }
+        response
+    }
+
+    /// Renders the boot and health screens.
+    pub(crate) fn render_index(&self, data: &IndexData) -> Response {
+        let response = match self.theme {
+            Theme::Award => HtmlTemplate(AwardIndex(data)).into_response(),
+            Theme::Ami => HtmlTemplate(AmiIndex(data)).into_response(),
+            Theme::Phoenix => HtmlTemplate(PhoenixIndex(data)).into_response(),
+        };
+        self.finish(response)
+    }
+
+    /// Renders the SETUP screen.
+    pub(crate) fn render_setup(&self, data: &SetupData) -> Response {
+        let response = match self.theme {
+            Theme::Award => HtmlTemplate(AwardSetup(data)).into_response(),
+            Theme::Ami => HtmlTemplate(AmiSetup(data)).into_response(),
+            Theme::Phoenix => HtmlTemplate(PhoenixSetup(data)).into_response(),
+        };
+        self.finish(response)
+    }
+
+    /// Renders the hub boot menu.
+    pub(crate) fn render_hub(&self, data: &HubData) -> Response {
+        let response = match self.theme {
+            Theme::Award => HtmlTemplate(AwardHub(data)).into_response(),
+            Theme::Ami => HtmlTemplate(AmiHub(data)).into_response(),
+            Theme::Phoenix => HtmlTemplate(PhoenixHub(data)).into_response(),
+        };
+        self.finish(response)
+    }
+
+    /// Renders the container listing.
+    pub(crate) fn render_containers(&self, data: &ContainersData) -> Response {
+        let response = match self.theme {
+            Theme::Award => HtmlTemplate(AwardContainers(data)).into_response(),
+            Theme::Ami => HtmlTemplate(AmiContainers(data)).into_response(),
+            Theme::Phoenix => HtmlTemplate(PhoenixContainers(data)).into_response(),
+        };
+        self.finish(response)
+    }
+
+    /// Renders the top processes table.
+    pub(crate) fn render_processes(&self, data: &ProcessesData) -> Response {
+        let response = match self.theme {
+            Theme::Award => HtmlTemplate(AwardProcesses(data)).into_response(),
+            Theme::Ami => HtmlTemplate(AmiProcesses(data)).into_response(),
+            Theme::Phoenix => HtmlTemplate(PhoenixProcesses(data)).into_response(),
+        };
+        self.finish(response)
+    }
+}
@@ -0,0 +1,304 @@
+//! RSPI-BIOS terminal client
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use sysinfo::{Networks, System};
+
+use crate::api::{ApiSnapshot, SystemInfo};
+use crate::client::ClientArgs;
+use crate::snapshot::SystemSnapshot;
+
+/// Inner width of the health screen box, fits an 80 column terminal.
+pub(crate) const WIDTH: usize = 76;
+
+const RESET: &str = "\x1b[0m";
+const CLEAR: &str = "\x1b[H\x1b[2J";
+const HIDE_CURSOR: &str = "\x1b[?25l";
+const SHOW_CURSOR: &str = "\x1b[?25h";
+/// Light grey on black like the boot screen.
+const BOOT_COLORS: &str = "\x1b[0;37;40m";
+/// Bright cyan on blue like the health screen.
+const HEALTH_COLORS: &str = "\x1b[0;96;44m";
+const HIGHLIGHT: &str = "\x1b[93m";
+
+/// `tui` subcommand, renders the boot and health screens in a terminal e.g. over SSH.
+#[derive(clap::Args, Debug)]
+pub(crate) struct TuiCommand {
+    /// Collect system information on this machine instead of asking a running instance
+    #[arg(long)]
+    local: bool,
+
+    /// How often to refresh the health screen
+    #[arg(long, value_parser = crate::parse_duration, default_value = "2")]
+    interval: Duration,
+
+    /// Go straight to the health screen
+    #[arg(long)]
+    skip_boot: bool,
+
+    /// Board model shown with `--local` if it can't be detected
+    #[arg(long, default_value = "Raspberry Pi 4 Model B Rev 1.4")]
+    model_name_fallback: String,
+
+    #[command(flatten)]
+    client: ClientArgs,
+}
+
+/// Where snapshots come from.
+enum Source {
+    /// `GET /api/snapshot` of a running instance
+    Remote {
+        client: reqwest::Client,
+        url: reqwest::Url,
+    },
+    Local {
+        system: Box<System>,
+        networks: Networks,
+        info: SystemInfo,
+    },
+}
+
+impl Source {
+    async fn fetch(&mut self) -> Result<ApiSnapshot, String> {
+        match self {
+            Self::Remote { client, url } => ApiSnapshot::fetch(client, url).await,
+            Self::Local {
+                system,
+                networks,
+                info,
+            } => Ok(ApiSnapshot {
+                system: info.clone(),
+                snapshot: SystemSnapshot::collect(system, networks, false),
+            }),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Remote { url, .. } => url.to_string(),
+            Self::Local { .. } => "this machine".to_string(),
+        }
+    }
+}
+
+/// Writes to `STDOUT` right away, errors are ignored since there is nowhere else to show them.
+fn print(s: &str) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(s.as_bytes());
+    let _ = stdout.flush();
+}
+
+/// Plays the boot screen like `award/index.html`.
+async fn boot(current: &ApiSnapshot) {
+    let system = &current.system;
+    print(&format!(
+        "{BOOT_COLORS}{CLEAR}\
+        Raspberry Pi Kernel {kernel}, An Energy Star Ally\n\
+        Copyright (C) 2012-2024, Raspberry Pi Foundation & Broadcom.\n\n\
+        {model} Kernel Version {kernel}\n\n\
+        Broadcom(R) BCM2711 {brand} ({count}) {speed} MHz\n",
+        kernel = system.kernel_version,
+        model = system.model_name,
+        brand = system.cpu_brand,
+        count = system.cpu_count,
+        speed = system.cpu_speed,
+    ));
+
+    let total_memory = current.snapshot.total_memory / 1_000;
+    let chunk = (total_memory / 100).max(1);
+    for memory in (0..total_memory).step_by(usize::try_from(chunk).unwrap_or(usize::MAX)) {
+        print(&format!("\rMemory Test :   {memory}K"));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    print(&format!("\rMemory Test :   {total_memory}K OK\n\n\n"));
+
+    for (delay, lines) in [
+        (
+            300,
+            "Award Plug and Play BIOS Extension v1.0A\n\
+            Initialize Plug and Play Cards...\n\
+            PNP init Completed\n\n",
+        ),
+        (100, "Detecting Primary Master .....: Boot EEPROM\n"),
+        (50, "Detecting Primary Slave ......: SanDisk SD\n"),
+        (
+            420,
+            "Detecting Secondary Master ...: Skip\n\
+            Detecting Secondary Slave ....: None\n",
+        ),
+    ] {
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        print(lines);
+    }
+    print(&format!(
+        "\n12/05/2023-04/BCM2711/RPI4B-UEFI-{}\n",
+        system.version
+    ));
+    tokio::time::sleep(Duration::from_millis(1_500)).await;
+}
+
+/// Cuts `s` to [`WIDTH`] characters and pads it with spaces.
+fn fit(s: &str) -> String {
+    let s: String = s.chars().take(WIDTH).collect();
+    format!("{s:<WIDTH$}")
+}
+
+/// Puts `left` and `right` at the edges of a line.
+fn spread(left: &str, right: &str) -> String {
+    let gap = WIDTH.saturating_sub(left.chars().count() + right.chars().count());
+    fit(&format!("{left}{:gap$}{right}", ""))
+}
+
+/// Text of the health screen like `award/index.html`, every line is [`WIDTH`] characters long.
+pub(crate) struct HealthLines {
+    /// Model, uptime and live counters
+    pub(crate) header: [String; 2],
+    /// Hardware and load, the first letter is highlighted like a menu shortcut
+    pub(crate) specs: Vec<String>,
+}
+
+impl HealthLines {
+    pub(crate) fn new(current: &ApiSnapshot) -> Self {
+        let system = &current.system;
+        let snapshot = &current.snapshot;
+        // Rounded the same way as the dashboard
+        let rounded_memory = (snapshot.total_memory / 1_000_000_000 + 3) & !3;
+        let disk_size = (system.disk_size / 1_000_000_000 + 7) & !7;
+
+        let header = [
+            spread(
+                &system.model_name,
+                &format!("Uptime: {} hours", (snapshot.uptime + 1_800) / 3_600),
+            ),
+            spread(
+                "",
+                &format!(
+                    "Process Count: {}   RX/TX: {}MB/{}MB",
+                    snapshot.process_count,
+                    (snapshot.rx + 500_000) / 1_000_000,
+                    (snapshot.tx + 500_000) / 1_000_000
+                ),
+            ),
+        ];
+        let specs = [
+            format!(
+                "CPU.......: Broadcom BCM2711, {} ({}) SoC @ {} MHz",
+                system.cpu_brand, system.cpu_arch, system.cpu_speed
+            ),
+            format!("RAM.......: {rounded_memory}GB LPDDR4-3200 SDRAM"),
+            format!("Storage...: {disk_size}GB Micro SD Card"),
+            "WIFI......: 2.4 GHz and 5.0 GHz IEEE 802.11ac wireless, Bluetooth 5.0, BLE"
+                .to_string(),
+            "Power.....: 5V DC via USB-C connector (minimum 3A*)".to_string(),
+            "PoE.......: Power over Ethernet (PoE) enabled (requires separate PoE HAT)".to_string(),
+            format!("OS........: {}", system.os_version),
+            format!("Host......: {}", system.host_name),
+            format!(
+                "Load......: CPU {:.1}%, Memory {}MB/{}MB",
+                snapshot.cpu_usage,
+                snapshot.used_memory / 1_048_576,
+                snapshot.total_memory / 1_048_576
+            ),
+        ];
+
+        Self {
+            header,
+            specs: specs.iter().map(|line| fit(line)).collect(),
+        }
+    }
+}
+
+/// Renders the health screen like `award/index.html`, `status` is shown below it.
+pub(crate) fn health_screen(current: &ApiSnapshot, status: &str) -> String {
+    let lines = HealthLines::new(current);
+
+    let mut screen = format!("{HEALTH_COLORS}{CLEAR}");
+    let _ = writeln!(screen, "\u{2554}{}\u{2557}", "\u{2550}".repeat(WIDTH + 2));
+    for line in lines.header {
+        let _ = writeln!(screen, "\u{2551} {line} \u{2551}");
+    }
+    let _ = writeln!(screen, "\u{255f}{}\u{2562}", "\u{2500}".repeat(WIDTH + 2));
+    for line in lines.specs {
+        let mut chars = line.chars();
+        let first = chars.next().unwrap_or(' ');
+        let _ = writeln!(
+            screen,
+            "\u{2551} {HIGHLIGHT}{first}{HEALTH_COLORS}{} \u{2551}",
+            chars.as_str()
+        );
+    }
+    let _ = writeln!(screen, "\u{255a}{}\u{255d}", "\u{2550}".repeat(WIDTH + 2));
+    let _ = write!(screen, "{RESET}{}", fit(status));
+    screen
+}
+
+/// Runs the `tui` subcommand until `CTRL+C`, logging isn't set up so errors are printed to `STDERR`.
+pub(crate) async fn run_command(command: TuiCommand) -> ExitCode {
+    let mut source = if command.local {
+        let mut system = System::new();
+        system.refresh_cpu_all();
+        let info = SystemInfo::local(
+            &system,
+            crate::detect_model_name(&command.model_name_fallback),
+        );
+        Source::Local {
+            system: Box::new(system),
+            networks: Networks::new_with_refreshed_list(),
+            info,
+        }
+    } else {
+        match (
+            command.client.client().await,
+            command.client.endpoint("api/snapshot"),
+        ) {
+            (Ok(client), Ok(url)) => Source::Remote { client, url },
+            (Err(e), _) | (_, Err(e)) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    };
+
+    let mut current = match source.fetch().await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!(
+                "Failed to get system information from {}. Error: {e}",
+                source.describe()
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    print(HIDE_CURSOR);
+    let show = async {
+        if !command.skip_boot {
+            boot(&current).await;
+        }
+
+        let mut interval = tokio::time::interval(command.interval);
+        loop {
+            interval.tick().await;
+            let status = match source.fetch().await {
+                Ok(s) => {
+                    current = s;
+                    format!("{} - CTRL+C to exit", source.describe())
+                }
+                Err(e) => format!("Connection lost, retrying. Error: {e}"),
+            };
+            print(&health_screen(&current, &status));
+        }
+    };
+
+    tokio::select! {
+        () = show => {},
+        _ = tokio::signal::ctrl_c() => {},
+    }
+    print(&format!("{RESET}{SHOW_CURSOR}\n"));
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,301 @@
+//! RSPI-BIOS systemd unit status
+//! Author: Piotr Placzek (piotrpdev) <https://github.com/piotrpdev>
+//! SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+use time::OffsetDateTime;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+
+use crate::AppState;
+
+const SYSTEMD: &str = "org.freedesktop.systemd1";
+const MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER: &str = "org.freedesktop.systemd1.Manager";
+
+/// CLI parameters for the unit status list, flattened into `Args`.
+#[derive(clap::Args, Clone, Debug)]
+pub(crate) struct UnitsArgs {
+    /// systemd unit to show in SETUP, `/api/units` and `units` SSE events e.g. `nginx.service`, can be repeated
+    #[arg(long = "status-unit")]
+    status_units: Vec<String>,
+
+    /// Also show every failed unit
+    #[arg(long)]
+    status_failed_units: bool,
+}
+
+impl UnitsArgs {
+    pub(crate) fn enabled(&self) -> bool {
+        !self.status_units.is_empty() || self.status_failed_units
+    }
+}
+
+/// "Boot Device Priority" label of a unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum UnitLabel {
+    /// Active or reloading
+    Enabled,
+    /// Inactive, not found or starting
+    Disabled,
+    /// Failed or waiting to be restarted after failing
+    Failed,
+}
+
+impl UnitLabel {
+    const fn new(active_state: &str, sub_state: &str) -> Self {
+        match (active_state.as_bytes(), sub_state.as_bytes()) {
+            (b"failed", _) | (_, b"auto-restart") => Self::Failed,
+            (b"active" | b"reloading", _) => Self::Enabled,
+            _ => Self::Disabled,
+        }
+    }
+
+    pub(crate) const fn as_str(self) -> &'static str {
+        match self {
+            Self::Enabled => "Enabled",
+            Self::Disabled => "Disabled",
+            Self::Failed => "Failed",
+        }
+    }
+}
+
+/// State of a systemd unit, sent in `units` SSE events and by `GET /api/units`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct UnitStatus {
+    pub(crate) name: String,
+    pub(crate) description: String,
+    /// e.g. `loaded`, `not-found`
+    pub(crate) load_state: String,
+    /// e.g. `active`, `inactive`, `failed`
+    pub(crate) active_state: String,
+    /// e.g. `running`, `exited`, `dead`
+    pub(crate) sub_state: String,
+    pub(crate) label: UnitLabel,
+    /// Automatic restarts since the unit was started by hand, [`None`] for units other than services
+    pub(crate) restarts: Option<u32>,
+    /// Seconds since the Unix epoch at which the active state last changed, [`None`] if it never did
+    pub(crate) state_change: Option<u64>,
+}
+
+impl UnitStatus {
+    /// Last state change as shown in SETUP, `-` if it never changed.
+    pub(crate) fn since(&self) -> String {
+        self.state_change
+            .and_then(|t| OffsetDateTime::from_unix_timestamp(i64::try_from(t).ok()?).ok())
+            .and_then(|t| {
+                t.format(time::macros::format_description!(
+                    "[year]-[month]-[day] [hour]:[minute] UTC"
+                ))
+                .ok()
+            })
+            .unwrap_or_else(|| "-".to_string())
+    }
+}
+
+/// Names boot devices like Award BIOS, the ones after "Third" are numbered.
+fn priority(index: usize) -> String {
+    match index {
+        0 => "First Boot Device".to_string(),
+        1 => "Second Boot Device".to_string(),
+        2 => "Third Boot Device".to_string(),
+        _ => format!("Boot Device {}", index + 1),
+    }
+}
+
+/// Pairs every unit with its [`priority`] for SETUP.
+pub(crate) fn prioritized(units: &[UnitStatus]) -> Vec<(String, UnitStatus)> {
+    units
+        .iter()
+        .enumerate()
+        .map(|(i, u)| (priority(i), u.clone()))
+        .collect()
+}
+
+/// Takes `name` out of `properties`, [`None`] if it is missing or has another type.
+fn property<T: TryFrom<OwnedValue>>(
+    properties: &mut HashMap<String, OwnedValue>,
+    name: &str,
+) -> Option<T> {
+    properties.remove(name).and_then(|v| T::try_from(v).ok())
+}
+
+async fn get_all(
+    connection: &zbus::Connection,
+    path: &OwnedObjectPath,
+    interface: &str,
+) -> zbus::Result<HashMap<String, OwnedValue>> {
+    connection
+        .call_method(
+            Some(SYSTEMD),
+            path,
+            Some("org.freedesktop.DBus.Properties"),
+            "GetAll",
+            &(interface,),
+        )
+        .await?
+        .body()
+        .deserialize()
+}
+
+async fn unit_status(connection: &zbus::Connection, name: &str) -> zbus::Result<UnitStatus> {
+    // Unlike `GetUnit`, also works for units that aren't loaded e.g. inactive or misspelled ones
+    let path: OwnedObjectPath = connection
+        .call_method(
+            Some(SYSTEMD),
+            MANAGER_PATH,
+            Some(MANAGER),
+            "LoadUnit",
+            &(name,),
+        )
+        .await?
+        .body()
+        .deserialize()?;
+
+    let mut unit = get_all(connection, &path, "org.freedesktop.systemd1.Unit").await?;
+    let restarts = if name.ends_with(".service") {
+        get_all(connection, &path, "org.freedesktop.systemd1.Service")
+            .await
+            .ok()
+            .and_then(|mut s| property(&mut s, "NRestarts"))
+    } else {
+        None
+    };
+
+    let active_state: String = property(&mut unit, "ActiveState").unwrap_or_default();
+    let sub_state: String = property(&mut unit, "SubState").unwrap_or_default();
+    Ok(UnitStatus {
+        name: name.to_string(),
+        description: property(&mut unit, "Description").unwrap_or_default(),
+        load_state: property(&mut unit, "LoadState").unwrap_or_default(),
+        label: UnitLabel::new(&active_state, &sub_state),
+        active_state,
+        sub_state,
+        restarts,
+        // In microseconds, 0 if it never changed
+        state_change: property(&mut unit, "StateChangeTimestamp")
+            .filter(|&t: &u64| t > 0)
+            .map(|t| t / 1_000_000),
+    })
+}
+
+/// Returns the names of every failed unit.
+pub(crate) async fn failed_units(connection: &zbus::Connection) -> zbus::Result<Vec<String>> {
+    type Unit = (
+        String,
+        String,
+        String,
+        String,
+        String,
+        String,
+        OwnedObjectPath,
+        u32,
+        String,
+        OwnedObjectPath,
+    );
+
+    let units: Vec<Unit> = connection
+        .call_method(
+            Some(SYSTEMD),
+            MANAGER_PATH,
+            Some(MANAGER),
+            "ListUnitsFiltered",
+            &(vec!["failed"],),
+        )
+        .await?
+        .body()
+        .deserialize()?;
+    Ok(units.into_iter().map(|u| u.0).collect())
+}
+
+/// Configured units in order followed by failed ones that aren't configured.
+async fn collect(connection: &zbus::Connection, args: &UnitsArgs) -> zbus::Result<Vec<UnitStatus>> {
+    let mut names = args.status_units.clone();
+    if args.status_failed_units {
+        for name in failed_units(connection).await? {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    let mut units = Vec::with_capacity(names.len());
+    for name in names {
+        match unit_status(connection, &name).await {
+            Ok(u) => units.push(u),
+            // Error reply for this unit e.g. an invalid name, the connection is fine
+            Err(zbus::Error::MethodError(_, message, _)) => units.push(UnitStatus {
+                name,
+                description: message.unwrap_or_default(),
+                load_state: "error".to_string(),
+                active_state: "inactive".to_string(),
+                sub_state: "dead".to_string(),
+                label: UnitLabel::Disabled,
+                restarts: None,
+                state_change: None,
+            }),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(units)
+}
+
+/// Asks systemd for the state of the units once every `system_refresh_interval` and sends it over
+/// `AppState::units_tx` when it changes.
+pub(crate) async fn watch_units(args: UnitsArgs, state: Arc<AppState>) {
+    if !args.enabled() {
+        return;
+    }
+    tracing::info!("Watching systemd units");
+
+    // Reused between intervals, reconnects after an error e.g. when D-Bus restarts
+    let mut connection: Option<zbus::Connection> = None;
+    let mut failing = false;
+    loop {
+        let result = match connection.take() {
+            Some(c) => Ok(c),
+            None => zbus::Connection::system().await,
+        };
+        let result = match result {
+            Ok(c) => {
+                let units = collect(&c, &args).await;
+                connection = units.is_ok().then_some(c);
+                units
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(units) => {
+                if failing {
+                    tracing::info!("Connected to systemd again");
+                    failing = false;
+                }
+                state.units_tx.send_if_modified(|current| {
+                    let changed = *current != units;
+                    *current = units;
+                    changed
+                });
+            }
+            Err(e) => {
+                // Logged once instead of every interval
+                if !failing {
+                    tracing::error!(error = %e, "Failed to get unit status from systemd");
+                    failing = true;
+                }
+            }
+        }
+
+        let system_refresh_interval = state.args.lock().await.system_refresh_interval;
+        tokio::time::sleep(system_refresh_interval).await;
+    }
+}
+
+/// Handles `GET` requests to `/api/units`, returns the latest [`UnitStatus`] of every unit.
+pub(crate) async fn units_handler(state: State<Arc<AppState>>) -> Json<Vec<UnitStatus>> {
+    Json(state.units_tx.borrow().clone())
+}